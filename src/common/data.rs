@@ -14,7 +14,12 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use super::utils::{rand_auth_token, rand_session_id};
+use crate::config::SessionIdFormat;
 use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fmt::{Display, Formatter},
+};
 
 /// A unique ID which identifies the session.
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -22,8 +27,14 @@ use serde::{Deserialize, Serialize};
 pub struct SessionId(String);
 
 impl SessionId {
-    pub fn new() -> Self {
-        Self(rand_session_id())
+    pub fn new(format: &SessionIdFormat) -> Self {
+        Self(rand_session_id(format))
+    }
+}
+
+impl Display for SessionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
@@ -44,3 +55,29 @@ impl AuthToken {
         Self(rand_auth_token())
     }
 }
+
+/// `PlayerToken` identifies a player across sessions, so that nobody else can
+/// play under their rating. Like `AuthToken`, it should be kept secret.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerToken(String);
+
+/// Player tokens chosen by clients must be at least this long, so that they
+/// are hard to guess.
+const MIN_PLAYER_TOKEN_LEN: usize = 16;
+
+impl PlayerToken {
+    pub fn new() -> Self {
+        Self(rand_auth_token())
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.0.len() >= MIN_PLAYER_TOKEN_LEN && self.0.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+}
+
+impl Display for PlayerToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
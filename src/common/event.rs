@@ -14,16 +14,19 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::{
-    data::UserId,
-    session::{Game, Session},
+    data::{Role, UserId},
+    session::{CloseReason, Game, GameResult, Session},
 };
 use bughouse_rs::infoCourier::infoCourier::gen_fen;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
+use std::time::Duration;
 
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Event<'a> {
+    /// The position of the event in the session's event stream.
+    pub id: usize,
     pub caused_by: UserId,
     #[serde(flatten)]
     pub ev: EventType,
@@ -31,35 +34,112 @@ pub struct Event<'a> {
 }
 
 impl<'a> Event<'a> {
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::ser::to_vec(self).unwrap()
+    }
+}
+
+/// `Payload` is a serialized `Event` numbered by its position in the session's
+/// event stream. The numbers let clients resume a stream after reconnecting.
+#[derive(Clone, Debug)]
+pub struct Payload {
+    pub id: usize,
+    pub json: Vec<u8>,
+}
+
+impl Payload {
     pub fn to_message(&self) -> Vec<u8> {
-        let mut msg = b"data: ".to_vec();
-        msg.append(&mut serde_json::ser::to_vec(self).unwrap());
+        let mut msg = format!("id: {}\ndata: ", self.id).into_bytes();
+        msg.extend(&self.json);
         msg.extend(b"\n\n");
         msg
     }
 }
 
+/// Tells the client how long to wait before reconnecting to a dropped event
+/// stream.
+pub fn retry_message(retry: Duration) -> Vec<u8> {
+    format!("retry: {}\n\n", retry.as_millis()).into_bytes()
+}
+
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "type")]
 pub enum EventType {
-    GameEnded { winners: Option<(UserId, UserId)> },
+    #[serde(rename_all = "camelCase")]
+    DrawOffered {
+        user_id: UserId,
+    },
+    /// The session will be closed in `expires_in` unless someone acts.
+    #[serde(rename_all = "camelCase")]
+    ExpiryWarning {
+        expires_in: Duration,
+    },
+    GameEnded(GameResult),
+    GameResumed,
     GameStarted,
     Joined,
+    /// A team clinched the match, or it ended in a tie.
+    MatchEnded {
+        winners: Option<(UserId, UserId)>,
+        score: (usize, usize),
+    },
+    /// The next game starts in `starts_in` unless it is started by hand.
+    #[serde(rename_all = "camelCase")]
+    NextGameIn {
+        starts_in: Duration,
+        active_participants: ((UserId, UserId), (UserId, UserId)),
+    },
     ParticipantsChanged,
     Periodic,
     PieceDeployed,
     PieceMoved,
     PiecePromoted,
+    QueueChanged,
+    #[serde(rename_all = "camelCase")]
+    RoleChanged {
+        user_id: UserId,
+        role: Role,
+    },
+    SessionClosed {
+        reason: CloseReason,
+    },
+    SettingsChanged,
+    Snapshot,
+    #[serde(rename_all = "camelCase")]
+    UserKicked {
+        user_id: UserId,
+        banned: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    UserLeft {
+        user_id: UserId,
+    },
+}
+
+impl EventType {
+    /// Returns `false` for events which don't change the saved state of the
+    /// session.
+    pub fn changes_state(&self) -> bool {
+        !matches!(
+            self,
+            Self::ExpiryWarning { .. } | Self::Periodic | Self::SessionClosed { .. }
+        )
+    }
 }
 
 impl Serialize for Game {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut game = serializer.serialize_struct("Game", 4)?;
+        let mut game = serializer.serialize_struct("Game", 9)?;
         game.serialize_field("activeParticipants", &self.active_participants)?;
         game.serialize_field("remainingTime", &self.remaining_time)?;
+        game.serialize_field("timeControl", &self.time_control)?;
         game.serialize_field("board", &gen_fen(&self.logic))?;
         game.serialize_field("pool", &self.logic.get_pools())?;
+        game.serialize_field("moves", &self.moves)?;
+        game.serialize_field("ply", &(self.ply(true), self.ply(false)))?;
+        game.serialize_field("paused", &!self.awaiting.is_empty())?;
+        game.serialize_field("drawOffer", &self.draw_offer)?;
         game.end()
     }
 }
@@ -20,4 +20,5 @@ pub mod req;
 pub mod resp;
 mod utils;
 
-pub use data::{AuthToken, SessionId};
+pub use data::{AuthToken, PlayerToken, SessionId};
+pub use utils::save_file;
@@ -13,9 +13,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::data::AuthToken;
-use crate::data::UserId;
+use super::data::{AuthToken, PlayerToken};
+use crate::data::{Role, TimeControl, UserId};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// `Create` is received when the user wants to create a new session.
 ///
@@ -24,6 +25,23 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "camelCase")]
 pub struct Create {
     pub owner_name: String,
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+    /// If `true`, the session is shown in the lobby listing.
+    #[serde(default)]
+    pub public: bool,
+    /// If `false`, spectators have to give a name. Defaults to `true`.
+    #[serde(default)]
+    pub anonymous_spectators: Option<bool>,
+    /// Countdown before the next game starts on its own once a game ended.
+    /// Defaults to zero, which means games are only started by hand. It may be
+    /// at most an hour.
+    #[serde(default)]
+    pub auto_start: Duration,
+    /// The owner's token from an earlier session, so that their games count
+    /// towards the same rating. A new one is issued if it is omitted.
+    #[serde(default)]
+    pub player_token: Option<PlayerToken>,
 }
 
 /// `Delete` is received when the session owner wants to end a session.
@@ -47,8 +65,72 @@ pub enum Join {
     #[serde(rename_all = "camelCase")]
     Connect { auth_token: AuthToken },
     /// The user wants to join an already existing session for the first time.
+    /// The player token works like the one in `Create`.
     #[serde(rename_all = "camelCase")]
-    Join { user_name: String },
+    Join {
+        user_name: String,
+        #[serde(default)]
+        player_token: Option<PlayerToken>,
+    },
+}
+
+/// `Spectate` is received when the user wants to watch a session without
+/// playing. The name can be omitted if the session allows anonymous
+/// spectators.
+///
+/// API endpoint: `POST /v1/sessions/:sid/spectators`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Spectate {
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// `Leave` is received when a user or a spectator wants to leave a session.
+/// Leaving during a game counts as a resignation.
+///
+/// API endpoint: `POST /v1/sessions/:sid/leave`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Leave {
+    pub auth_token: AuthToken,
+}
+
+/// `Kick` is received when the session owner wants to remove a user from the
+/// session. Banned users can't join again under the same name or player
+/// token.
+///
+/// API endpoint: `POST /v1/sessions/:sid/kick`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Kick {
+    pub auth_token: AuthToken,
+    pub user_id: UserId,
+    #[serde(default)]
+    pub ban: bool,
+}
+
+/// `Transfer` is received when the session owner wants to hand the session over
+/// to another user. The previous owner becomes a moderator.
+///
+/// API endpoint: `POST /v1/sessions/:sid/owner`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transfer {
+    pub auth_token: AuthToken,
+    pub user_id: UserId,
+}
+
+/// `Appoint` is received when the session owner wants to change the role of a
+/// user. Ownership can only be changed with `Transfer`.
+///
+/// API endpoint: `POST /v1/sessions/:sid/roles`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Appoint {
+    pub auth_token: AuthToken,
+    pub user_id: UserId,
+    pub role: Role,
 }
 
 /// `Start` is received when the session owner wants to start a game.
@@ -60,6 +142,16 @@ pub struct Start {
     pub auth_token: AuthToken,
 }
 
+/// `Abort` is received when the session owner wants to cancel the current game
+/// without declaring a winner.
+///
+/// API endpoint: `DELETE /v1/sessions/:sid/games/:gid`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Abort {
+    pub auth_token: AuthToken,
+}
+
 /// `Resign` is received when an active participant wants to surrender.
 ///
 /// API endpoint: `POST /v1/sessions/:sid/games/:gid`
@@ -69,8 +161,24 @@ pub struct Resign {
     pub auth_token: AuthToken,
 }
 
+/// `Draw` is received when an active participant offers a draw, or accepts the
+/// draw offered by the other team. Offering a draw when the other team already
+/// did accepts it. Offers expire once a move is made on either board.
+///
+/// API endpoint: `POST /v1/sessions/:sid/games/:gid/draw`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum Draw {
+    #[serde(rename_all = "camelCase")]
+    Offer { auth_token: AuthToken },
+    #[serde(rename_all = "camelCase")]
+    Accept { auth_token: AuthToken },
+}
+
 /// `Board` is received when the user wants to modify the state of the
-/// chessboard.
+/// chessboard. If `ply` is given, the request is only applied if that many
+/// moves were made on the user's board so far.
 ///
 /// API endpoint: `POST /v1/sessions/:sid/games/:gid/board`
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -83,12 +191,16 @@ pub enum Board {
         auth_token: AuthToken,
         piece: String,
         pos: String,
+        #[serde(default)]
+        ply: Option<usize>,
     },
     /// The user wants to move a piece.
     #[serde(rename_all = "camelCase")]
     Move {
         auth_token: AuthToken,
         change: String,
+        #[serde(default)]
+        ply: Option<usize>,
     },
     /// The user wants to make a move that will result in upgrade of a piece.
     #[serde(rename_all = "camelCase")]
@@ -96,6 +208,52 @@ pub enum Board {
         auth_token: AuthToken,
         change: String,
         upgrade_to: String,
+        #[serde(default)]
+        ply: Option<usize>,
+    },
+}
+
+/// `Match` is received when the session owner wants the same two teams to play
+/// a best-of-`best_of` series, where `best_of` is odd and at most 99. The
+/// teams are written as ((white, black), (black, white)) for the first game,
+/// after which the seats rotate.
+///
+/// API endpoint: `POST /v1/sessions/:sid/match`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Match {
+    pub auth_token: AuthToken,
+    pub teams: ((UserId, UserId), (UserId, UserId)),
+    pub best_of: usize,
+}
+
+/// `Queue` is received when the session owner wants to change the upcoming
+/// games. Positions count from 0, which is the next game.
+///
+/// API endpoint: `POST /v1/sessions/:sid/queue`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum Queue {
+    /// The owner wants a game to be played earlier or later.
+    #[serde(rename_all = "camelCase")]
+    Move {
+        auth_token: AuthToken,
+        from: usize,
+        to: usize,
+    },
+    /// The owner wants a game not to be played.
+    #[serde(rename_all = "camelCase")]
+    Skip { auth_token: AuthToken, pos: usize },
+    /// The owner wants a game with the given players, written as
+    /// ((white, black), (black, white)). Without a position, the game is
+    /// played after the others.
+    #[serde(rename_all = "camelCase")]
+    Insert {
+        auth_token: AuthToken,
+        #[serde(default)]
+        pos: Option<usize>,
+        pairing: ((UserId, UserId), (UserId, UserId)),
     },
 }
 
@@ -109,3 +267,45 @@ pub struct Participants {
     pub auth_token: AuthToken,
     pub participants: Vec<UserId>,
 }
+
+/// `Settings` is received when the session owner wants to change the settings
+/// which apply to the upcoming games.
+///
+/// API endpoint: `POST /v1/sessions/:sid/settings`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Settings {
+    pub auth_token: AuthToken,
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+    #[serde(default)]
+    pub public: Option<bool>,
+    #[serde(default)]
+    pub anonymous_spectators: Option<bool>,
+    #[serde(default)]
+    pub auto_start: Option<Duration>,
+}
+
+/// `Frame` is received over a WebSocket connection after the user has
+/// authenticated with a `Join::Connect` frame. The authentication token can be
+/// omitted, since the connection is already bound to a user.
+///
+/// API endpoint: `GET /v1/sessions/:sid/ws`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "camelCase")]
+pub enum Frame {
+    Abort(Abort),
+    Appoint(Appoint),
+    Board(Board),
+    Draw(Draw),
+    Kick(Kick),
+    Leave(Leave),
+    Match(Match),
+    Participants(Participants),
+    Queue(Queue),
+    Resign(Resign),
+    Settings(Settings),
+    Start(Start),
+    Transfer(Transfer),
+}
@@ -13,8 +13,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::data::{AuthToken, SessionId};
-use crate::{data::UserId, session::Session};
+use super::data::{AuthToken, PlayerToken, SessionId};
+use crate::{
+    data::UserId,
+    ratings::{Player, Team},
+    session::{Game, GameResult, Move, Session},
+};
 use serde::Serialize;
 
 /// `Created` is sent when a session is successfully created as per user
@@ -24,13 +28,18 @@ use serde::Serialize;
 pub struct Created<'a> {
     pub session_id: &'a SessionId,
     pub auth_token: &'a AuthToken,
+    /// Identifies the owner in later sessions.
+    pub player_token: &'a PlayerToken,
 }
 
 /// `Joined` is sent when a user succesfully joins a session for the first time.
+/// Spectators get no player token.
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Joined<'a> {
     pub auth_token: &'a AuthToken,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_token: Option<&'a PlayerToken>,
 }
 
 /// `Connected` is sent when a user succesfully connects to a session.
@@ -40,3 +49,84 @@ pub struct Connected<'a> {
     pub user_id: &'a UserId,
     pub session: &'a Session,
 }
+
+/// `State` is sent when a user or a spectator requests the state of a session.
+///
+/// API endpoint: `GET /v1/sessions/:sid`
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct State<'a> {
+    pub session: &'a Session,
+}
+
+/// `Position` is sent when a game is started or changed by a user. It holds the
+/// boards and the clocks right after the request was applied, along with the
+/// result if the request ended the game.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position<'a> {
+    pub game_id: usize,
+    pub game: &'a Game,
+    pub result: Option<&'a GameResult>,
+}
+
+/// `Moves` is sent when a user requests the move log of a game.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Moves<'a> {
+    pub moves: &'a [Move],
+}
+
+/// `Lobby` is sent when a user requests the list of public sessions.
+///
+/// API endpoint: `GET /v1/sessions`
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lobby {
+    pub sessions: Vec<Listing>,
+}
+
+/// `Listing` summarizes a public session for the lobby.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Listing {
+    pub session_id: SessionId,
+    pub owner_name: String,
+    pub users: usize,
+    pub max_users: usize,
+    pub participants: usize,
+    /// One of `starting`, `started` and `ended`.
+    pub game_state: &'static str,
+    pub game_running: bool,
+}
+
+/// `Ratings` is sent when a user requests the leaderboard. Players and teams
+/// are sorted by rating, the best first.
+///
+/// API endpoint: `GET /v1/ratings`
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ratings {
+    pub players: Vec<Player>,
+    pub teams: Vec<Team>,
+}
+
+/// `Error` is sent when a request can't be fulfilled.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Error<'a> {
+    /// A stable code which identifies the error.
+    pub error: &'a str,
+    /// A human readable description of the error.
+    pub message: &'a str,
+}
+
+/// `Status` is sent when a user requests the server status.
+///
+/// API endpoint: `GET /v1/status`
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub sessions: usize,
+    pub max_sessions: usize,
+}
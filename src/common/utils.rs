@@ -13,15 +13,72 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use rand::{distributions, thread_rng, Rng};
-use std::iter::repeat;
+use crate::config::SessionIdFormat;
+use rand::{distributions, seq::SliceRandom, thread_rng, Rng};
+use std::{iter::repeat, path::Path};
+use tokio::sync::Mutex;
+
+const ADJECTIVES: &[&str] = &[
+    "bold", "brave", "bright", "calm", "clever", "daring", "eager", "fast", "fierce", "gentle",
+    "grand", "happy", "keen", "lively", "lucky", "mighty", "noble", "proud", "quick", "quiet",
+    "rapid", "royal", "sharp", "silent", "sly", "steady", "swift", "wild", "wise", "witty",
+];
+
+const NOUNS: &[&str] = &[
+    "bishop",
+    "board",
+    "castle",
+    "check",
+    "clock",
+    "crown",
+    "dragon",
+    "duke",
+    "fianchetto",
+    "file",
+    "gambit",
+    "king",
+    "knight",
+    "pawn",
+    "queen",
+    "rank",
+    "rook",
+    "square",
+    "tempo",
+    "tower",
+];
 
 pub fn rand_auth_token() -> String {
     rand_alphanum_string(32)
 }
 
-pub fn rand_session_id() -> String {
-    rand_alphanum_string(4)
+pub fn rand_session_id(format: &SessionIdFormat) -> String {
+    let mut rng = thread_rng();
+    match format {
+        SessionIdFormat::Chars { alphabet, len } => (0..*len)
+            .filter_map(|_| alphabet.choose(&mut rng))
+            .collect(),
+        SessionIdFormat::Words => format!(
+            "{}-{}-{}",
+            ADJECTIVES.choose(&mut rng).unwrap(),
+            NOUNS.choose(&mut rng).unwrap(),
+            rng.gen_range(0, 100)
+        ),
+    }
+}
+
+/// Writes `data` to the file at `path`, unless a later version of it was
+/// already written. `written` holds the version which was last written. The
+/// data goes to a temporary file first, so that a crash can't leave a
+/// half-written file behind.
+pub async fn save_file(path: &Path, data: Vec<u8>, version: usize, written: &Mutex<usize>) {
+    let mut written = written.lock().await;
+    if version <= *written {
+        return;
+    }
+    let tmp = path.with_extension("tmp");
+    if tokio::fs::write(&tmp, data).await.is_ok() && tokio::fs::rename(&tmp, path).await.is_ok() {
+        *written = version;
+    }
 }
 
 fn rand_alphanum_string(len: usize) -> String {
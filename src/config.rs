@@ -15,6 +15,7 @@
 
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
     time::Duration,
 };
 
@@ -25,10 +26,33 @@ pub struct Config {
     bind_addr: SocketAddr,
     max_session: usize,
     session_capacity: usize,
-    tick: Duration,
     broadcast_interval: Duration,
     max_user: usize,
+    max_spectator: usize,
     max_participant: usize,
+    data_dir: Option<PathBuf>,
+    ratings_file: Option<PathBuf>,
+    session_id_format: SessionIdFormat,
+    lobby_timeout: Duration,
+    game_timeout: Duration,
+    expiry_warning: Duration,
+    owner_timeout: Duration,
+}
+
+/// `SessionIdFormat` determines what newly generated session IDs look like.
+#[derive(Clone, Debug)]
+pub enum SessionIdFormat {
+    /// `len` random characters from `alphabet`, e.g. `aX3b`.
+    Chars { alphabet: Vec<char>, len: usize },
+    /// Two random words and a number, e.g. `brave-knight-42`.
+    Words,
+}
+
+impl SessionIdFormat {
+    pub fn alphanumeric(len: usize) -> Self {
+        let alphabet = ('a'..='z').chain('A'..='Z').chain('0'..='9').collect();
+        Self::Chars { alphabet, len }
+    }
 }
 
 impl Config {
@@ -56,10 +80,6 @@ impl Config {
         self.session_capacity
     }
 
-    pub fn tick(&self) -> Duration {
-        self.tick
-    }
-
     pub fn broadcast_interval(&self) -> Duration {
         self.broadcast_interval
     }
@@ -68,9 +88,50 @@ impl Config {
         self.max_user
     }
 
+    pub fn max_spectator(&self) -> usize {
+        self.max_spectator
+    }
+
     pub fn max_participant(&self) -> usize {
         self.max_participant
     }
+
+    pub fn data_dir(&self) -> Option<&Path> {
+        self.data_dir.as_deref()
+    }
+
+    /// The file which holds the player ratings. Defaults to `_ratings` in the
+    /// data directory, a name no session ID can have.
+    pub fn ratings_file(&self) -> Option<PathBuf> {
+        self.ratings_file
+            .clone()
+            .or_else(|| Some(self.data_dir()?.join("_ratings")))
+    }
+
+    pub fn session_id_format(&self) -> &SessionIdFormat {
+        &self.session_id_format
+    }
+
+    /// How long a session without a running game may stay idle.
+    pub fn lobby_timeout(&self) -> Duration {
+        self.lobby_timeout
+    }
+
+    /// How long a session with a running game may stay idle.
+    pub fn game_timeout(&self) -> Duration {
+        self.game_timeout
+    }
+
+    /// How long before an idle session expires the users are warned.
+    pub fn expiry_warning(&self) -> Duration {
+        self.expiry_warning
+    }
+
+    /// How long the owner may be disconnected before ownership is handed to
+    /// another user.
+    pub fn owner_timeout(&self) -> Duration {
+        self.owner_timeout
+    }
 }
 
 impl Default for Config {
@@ -81,10 +142,17 @@ impl Default for Config {
             bind_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8080),
             max_session: 10,
             session_capacity: 4,
-            tick: Duration::from_secs(2),
             broadcast_interval: Duration::from_secs(20),
             max_user: 20,
+            max_spectator: 50,
             max_participant: 5,
+            data_dir: None,
+            ratings_file: None,
+            session_id_format: SessionIdFormat::alphanumeric(4),
+            lobby_timeout: Duration::from_secs(30 * 60),
+            game_timeout: Duration::from_secs(10 * 60),
+            expiry_warning: Duration::from_secs(60),
+            owner_timeout: Duration::from_secs(5 * 60),
         }
     }
 }
@@ -130,11 +198,6 @@ impl Builder {
         self
     }
 
-    pub fn tick(&mut self, value: Duration) -> &mut Self {
-        self.config.tick = value;
-        self
-    }
-
     pub fn broadcast_interval(&mut self, value: Duration) -> &mut Self {
         self.config.broadcast_interval = value;
         self
@@ -145,8 +208,48 @@ impl Builder {
         self
     }
 
+    pub fn max_spectator(&mut self, value: usize) -> &mut Self {
+        self.config.max_spectator = value;
+        self
+    }
+
     pub fn max_participant(&mut self, value: usize) -> &mut Self {
         self.config.max_participant = value;
         self
     }
+
+    pub fn data_dir<T: Into<PathBuf>>(&mut self, value: T) -> &mut Self {
+        self.config.data_dir = Some(value.into());
+        self
+    }
+
+    pub fn ratings_file<T: Into<PathBuf>>(&mut self, value: T) -> &mut Self {
+        self.config.ratings_file = Some(value.into());
+        self
+    }
+
+    pub fn session_id_format(&mut self, value: SessionIdFormat) -> &mut Self {
+        self.config.session_id_format = value;
+        self
+    }
+
+    pub fn lobby_timeout(&mut self, value: Duration) -> &mut Self {
+        self.config.lobby_timeout = value;
+        self
+    }
+
+    pub fn game_timeout(&mut self, value: Duration) -> &mut Self {
+        self.config.game_timeout = value;
+        self
+    }
+
+    pub fn expiry_warning(&mut self, value: Duration) -> &mut Self {
+        self.config.expiry_warning = value;
+        self
+    }
+
+    pub fn owner_timeout(&mut self, value: Duration) -> &mut Self {
+        self.config.owner_timeout = value;
+        self
+    }
 }
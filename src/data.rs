@@ -21,9 +21,10 @@ use std::{
     fmt::{Display, Formatter},
     num::ParseIntError,
     str::FromStr,
+    time::Duration,
 };
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 #[serde(try_from = "&str")]
 #[serde(into = "String")]
 pub struct UserId(u8);
@@ -94,10 +95,12 @@ impl Into<String> for Token {
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct User {
     name: String,
     score: usize,
+    #[serde(default)]
+    role: Role,
 }
 
 impl User {
@@ -108,7 +111,11 @@ impl User {
         {
             None
         } else {
-            Some(User { name, score: 0 })
+            Some(User {
+                name,
+                score: 0,
+                role: Role::default(),
+            })
         }
     }
 
@@ -123,4 +130,132 @@ impl User {
     pub fn score_mut(&mut self) -> &mut usize {
         &mut self.score
     }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn role_mut(&mut self) -> &mut Role {
+        &mut self.role
+    }
+}
+
+/// `Role` determines what a user may do in a session. Each role may do
+/// everything the roles before it may do.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    /// May watch the games, but not play.
+    Spectator,
+    /// May play when chosen as a participant.
+    #[default]
+    Player,
+    /// May start and abort games, choose the participants and kick users.
+    Moderator,
+    /// May change the settings, appoint moderators and delete the session.
+    Owner,
+}
+
+/// `TimeControl` determines how much time the players have for a game.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeControl {
+    /// Time each player has at the start of a game.
+    pub base: Duration,
+    /// Time added to the player's clock after each of their moves (Fischer).
+    pub increment: Duration,
+    pub delay: Delay,
+    /// Time added to the player's clock when they promote a piece.
+    pub promotion_bonus: Duration,
+}
+
+/// The longest time any part of a time control may take. This keeps the
+/// clocks far away from overflowing.
+const MAX_TIME_CONTROL: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl TimeControl {
+    pub fn is_valid(&self) -> bool {
+        let delay = match self.delay {
+            Delay::None => Duration::from_secs(0),
+            Delay::Bronstein { delay } | Delay::Simple { delay } => delay,
+        };
+        self.base > Duration::from_secs(0)
+            && self.base <= MAX_TIME_CONTROL
+            && self.increment <= MAX_TIME_CONTROL
+            && delay <= MAX_TIME_CONTROL
+            && self.promotion_bonus <= MAX_TIME_CONTROL
+    }
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_secs(300),
+            increment: Duration::from_secs(0),
+            delay: Delay::None,
+            promotion_bonus: Duration::from_secs(3),
+        }
+    }
+}
+
+/// `Delay` is the time a player can spend on a move without losing any time
+/// from their clock.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum Delay {
+    None,
+    /// The time spent on a move is given back after the move, up to `delay`.
+    Bronstein {
+        delay: Duration,
+    },
+    /// The clock starts running after `delay` has passed.
+    Simple {
+        delay: Duration,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_controls_are_bounded() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let too_long = day + Duration::from_secs(1);
+        let valid = TimeControl {
+            base: day,
+            increment: day,
+            delay: Delay::Simple { delay: day },
+            promotion_bonus: day,
+        };
+        assert!(valid.is_valid());
+        assert!(TimeControl::default().is_valid());
+        let invalid = [
+            TimeControl {
+                base: Duration::from_secs(0),
+                ..valid
+            },
+            TimeControl {
+                base: too_long,
+                ..valid
+            },
+            TimeControl {
+                increment: too_long,
+                ..valid
+            },
+            TimeControl {
+                delay: Delay::Bronstein { delay: too_long },
+                ..valid
+            },
+            TimeControl {
+                promotion_bonus: too_long,
+                ..valid
+            },
+        ];
+        for tc in &invalid {
+            assert!(!tc.is_valid(), "{:?}", tc);
+        }
+    }
 }
@@ -13,10 +13,31 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::{session, sessions};
+
 pub enum Error {
     Hyper(hyper::Error),
     InvalidRequest,
     InvalidResource,
+    /// The server can't take any more sessions.
+    Full,
+    /// The session rejected the request.
+    Session(session::Error),
+}
+
+impl From<sessions::Error> for Error {
+    fn from(err: sessions::Error) -> Self {
+        match err {
+            sessions::Error::InvalidName => Self::Session(session::Error::InvalidName),
+            sessions::Error::Full => Self::Full,
+        }
+    }
+}
+
+impl From<session::Error> for Error {
+    fn from(err: session::Error) -> Self {
+        Self::Session(err)
+    }
 }
 
 impl<T> From<tokio::sync::mpsc::error::SendError<T>> for Error {
@@ -42,3 +63,9 @@ impl From<serde_json::Error> for Error {
         Self::InvalidRequest
     }
 }
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(_: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::InvalidRequest
+    }
+}
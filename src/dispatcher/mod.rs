@@ -16,11 +16,12 @@
 mod error;
 mod utils;
 mod v1;
+mod ws;
 
-use crate::sessions::Sessions;
+use crate::{session, sessions::Sessions};
 use error::Error;
-use hyper::{Body, Response};
-use utils::{bad_request, not_found};
+use hyper::{Body, Response, StatusCode};
+use utils::{bad_request, error_response, not_found, service_unavailable};
 
 type Request = hyper::Request<Body>;
 
@@ -39,5 +40,35 @@ pub async fn dispatch(sessions: Sessions, req: Request) -> StdResult<Response<Bo
         Err(Error::Hyper(err)) => Err(err),
         Err(Error::InvalidRequest) => Ok(bad_request()),
         Err(Error::InvalidResource) => Ok(not_found()),
+        Err(Error::Full) => Ok(service_unavailable(
+            "tooManySessions",
+            "The server has reached its session limit.",
+        )),
+        Err(Error::Session(err)) => Ok(error_response(status(err), err.code(), err.message())),
+    }
+}
+
+fn status(err: session::Error) -> StatusCode {
+    use session::Error::*;
+    match err {
+        InvalidName | InvalidPlayerToken | InvalidTimeControl | InvalidAutoStart
+        | InvalidNotation | UnknownUser | InvalidRole | NotPlayer | InvalidPairing
+        | InvalidQueuePosition | InvalidSeriesLength => StatusCode::BAD_REQUEST,
+        Unauthorized | NotOwner | NotModerator | NotParticipant | AnonymousSpectator | Banned => {
+            StatusCode::FORBIDDEN
+        }
+        IllegalMove => StatusCode::UNPROCESSABLE_ENTITY,
+        SessionFull
+        | SpectatorsFull
+        | OwnerCannotLeave
+        | NotYourTurn
+        | OutOfTime
+        | StalePosition
+        | NoDrawOffer
+        | GameNotRunning
+        | GameRunning
+        | GamePaused
+        | InvalidParticipantCount
+        | NoPairings => StatusCode::CONFLICT,
     }
 }
@@ -13,17 +13,27 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::common::resp;
 use hyper::{
-    header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONNECTION, CONTENT_TYPE},
+    header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONNECTION, CONTENT_TYPE, RETRY_AFTER},
     http::response::Builder,
     Body, Response, StatusCode,
 };
+use std::time::Duration;
+
+/// How long clients should wait before retrying a request which failed
+/// because the server was at capacity.
+const RETRY_AFTER_DURATION: Duration = Duration::from_secs(30);
 
 // TODO: Don't set Access-Control-Allow-Origin to *
 pub fn builder() -> Builder {
     Response::builder().header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
 }
 
+pub fn bpgn_builder() -> Builder {
+    builder().header(CONTENT_TYPE, "application/x-bpgn; charset=UTF-8")
+}
+
 pub fn event_stream_builder() -> Builder {
     builder()
         .header(CONNECTION, "keep-alive")
@@ -38,23 +48,47 @@ pub fn to_json<T: Into<Body>>(t: T) -> Response<Body> {
     json_builder().body(t.into()).unwrap()
 }
 
-pub fn accepted() -> Response<Body> {
+pub fn to_bpgn<T: Into<Body>>(t: T) -> Response<Body> {
+    bpgn_builder().body(t.into()).unwrap()
+}
+
+pub fn no_content() -> Response<Body> {
     builder()
-        .status(StatusCode::ACCEPTED)
+        .status(StatusCode::NO_CONTENT)
         .body(Body::empty())
         .unwrap()
 }
 
 pub fn bad_request() -> Response<Body> {
-    builder()
-        .status(StatusCode::BAD_REQUEST)
-        .body(Body::empty())
-        .unwrap()
+    error_response(
+        StatusCode::BAD_REQUEST,
+        "invalidRequest",
+        "The request is malformed.",
+    )
 }
 
 pub fn not_found() -> Response<Body> {
-    builder()
-        .status(StatusCode::NOT_FOUND)
-        .body(Body::empty())
+    error_response(
+        StatusCode::NOT_FOUND,
+        "notFound",
+        "The requested resource does not exist.",
+    )
+}
+
+pub fn service_unavailable(error: &str, message: &str) -> Response<Body> {
+    let mut resp = error_response(StatusCode::SERVICE_UNAVAILABLE, error, message);
+    resp.headers_mut()
+        .insert(RETRY_AFTER, RETRY_AFTER_DURATION.as_secs().into());
+    resp
+}
+
+pub fn error_response(status: StatusCode, error: &str, message: &str) -> Response<Body> {
+    json_builder()
+        .status(status)
+        .body(to_error_json(error, message).into())
         .unwrap()
 }
+
+pub fn to_error_json(error: &str, message: &str) -> Vec<u8> {
+    serde_json::to_vec(&resp::Error { error, message }).unwrap()
+}
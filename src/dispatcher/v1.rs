@@ -13,29 +13,55 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::{error::Error, utils::*, Request, Result};
-use crate::{common::*, session::Msg, sessions::Sessions};
+use super::{error::Error, utils::*, ws, Request, Result};
+use crate::{
+    common::*,
+    session::{Msg, Presence},
+    sessions::Sessions,
+};
 use hyper::{body, Body, Method};
-use tokio::sync::{mpsc, oneshot};
+use std::time::Duration;
+use tokio::{
+    stream::{self, StreamExt},
+    sync::{mpsc, oneshot},
+};
+
+const AUTH_TOKEN: &str = "authToken";
+const BPGN_EXTENSION: &str = ".bpgn";
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+const SSE_RETRY: Duration = Duration::from_secs(3);
 
 pub async fn dispatch(sessions: Sessions, parts: &[&str], req: Request) -> Result {
     match parts.split_first() {
         Some((&"sessions", rest)) => dispatch_sessions(sessions, rest, req).await,
+        Some((&"ratings", [])) if req.method() == &Method::GET => {
+            let json = serde_json::to_vec(&sessions.ratings().leaderboard())?;
+            Ok(to_json(json))
+        }
+        Some((&"status", [])) if req.method() == &Method::GET => {
+            let json = serde_json::to_vec(&resp::Status {
+                sessions: sessions.len().await,
+                max_sessions: sessions.max_len(),
+            })?;
+            Ok(to_json(json))
+        }
         _ => Err(Error::InvalidResource),
     }
 }
 
 async fn dispatch_sessions(sessions: Sessions, parts: &[&str], req: Request) -> Result {
-    if parts.is_empty() && req.method() == &Method::POST {
+    if parts.is_empty() && req.method() == &Method::GET {
+        let json = serde_json::to_vec(&resp::Lobby {
+            sessions: sessions.list().await,
+        })?;
+        Ok(to_json(json))
+    } else if parts.is_empty() && req.method() == &Method::POST {
         let json = body::to_bytes(req.into_body()).await?;
         let req = serde_json::from_slice::<req::Create>(&json)?;
-        let mut session = sessions
-            .spawn(&req.owner_name)
-            .await
-            .ok_or(Error::InvalidRequest)?;
+        let mut session = sessions.spawn(&req.owner_name).await?;
         let (tx, rx) = oneshot::channel();
         session.send(Msg::C(req, tx)).await?;
-        Ok(to_json(rx.await?))
+        Ok(to_json(rx.await??))
     } else if let Some((&sid, rest)) = parts.split_first() {
         let session = sessions
             .get(&sid.into())
@@ -49,49 +75,198 @@ async fn dispatch_sessions(sessions: Sessions, parts: &[&str], req: Request) ->
 
 async fn dispatch_session(mut session: mpsc::Sender<Msg>, parts: &[&str], req: Request) -> Result {
     match (parts, req.method()) {
+        ([], &Method::GET) => {
+            let auth_token = auth_token_param(&req);
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::State(auth_token, tx)).await?;
+            Ok(to_json(rx.await??))
+        }
         ([], &Method::DELETE) => {
             let json = body::to_bytes(req.into_body()).await?;
             let req = serde_json::from_slice::<req::Delete>(&json)?;
-            session.send(Msg::D(req)).await?;
-            Ok(accepted())
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::D(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
         }
         ([], &Method::POST) => {
             let json = body::to_bytes(req.into_body()).await?;
             let req = serde_json::from_slice::<req::Join>(&json)?;
             let (tx, rx) = oneshot::channel();
             session.send(Msg::J(req, tx)).await?;
-            Ok(to_json(rx.await?))
+            Ok(to_json(rx.await??))
         }
         (["games"], &Method::POST) => {
             let json = body::to_bytes(req.into_body()).await?;
             let req = serde_json::from_slice::<req::Start>(&json)?;
-            session.send(Msg::S(req)).await?;
-            Ok(accepted())
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::S(req, tx)).await?;
+            Ok(to_json(rx.await??))
         }
         (["games", _], &Method::POST) => {
             let json = body::to_bytes(req.into_body()).await?;
             let req = serde_json::from_slice::<req::Resign>(&json)?;
-            session.send(Msg::R(req)).await?;
-            Ok(accepted())
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::R(req, tx)).await?;
+            Ok(to_json(rx.await??))
+        }
+        (["games", _], &Method::DELETE) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Abort>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Abort(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
         }
         (["games", _, "board"], &Method::POST) => {
             let json = body::to_bytes(req.into_body()).await?;
             let req = serde_json::from_slice::<req::Board>(&json)?;
-            session.send(Msg::B(req)).await?;
-            Ok(accepted())
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::B(req, tx)).await?;
+            Ok(to_json(rx.await??))
+        }
+        (["games", _, "draw"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Draw>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Draw(req, tx)).await?;
+            Ok(to_json(rx.await??))
+        }
+        (["games", file], &Method::GET) if file.ends_with(BPGN_EXTENSION) => {
+            let gid = file.trim_end_matches(BPGN_EXTENSION);
+            let gid = gid.parse().or(Err(Error::InvalidResource))?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Bpgn(gid, tx)).await?;
+            Ok(to_bpgn(rx.await?.ok_or(Error::InvalidResource)?))
+        }
+        (["games", gid, "moves"], &Method::GET) => {
+            let gid = gid.parse().or(Err(Error::InvalidResource))?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Moves(gid, tx)).await?;
+            Ok(to_json(rx.await?.ok_or(Error::InvalidResource)?))
+        }
+        (["kick"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Kick>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Kick(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
+        }
+        (["leave"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Leave>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Leave(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
+        }
+        (["match"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Match>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Match(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
+        }
+        (["owner"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Transfer>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Transfer(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
+        }
+        (["roles"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Appoint>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Appoint(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
         }
         (["participants"], &Method::POST) => {
             let json = body::to_bytes(req.into_body()).await?;
             let req = serde_json::from_slice::<req::Participants>(&json)?;
-            session.send(Msg::P(req)).await?;
-            Ok(accepted())
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::P(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
+        }
+        (["queue"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Queue>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Queue(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
+        }
+        (["spectators"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Spectate>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Spectate(req, tx)).await?;
+            Ok(to_json(rx.await??))
+        }
+        (["settings"], &Method::POST) => {
+            let json = body::to_bytes(req.into_body()).await?;
+            let req = serde_json::from_slice::<req::Settings>(&json)?;
+            let (tx, rx) = oneshot::channel();
+            session.send(Msg::Settings(req, tx)).await?;
+            rx.await??;
+            Ok(no_content())
         }
         (["sse"], &Method::GET) => {
+            let last_event_id = req
+                .headers()
+                .get(LAST_EVENT_ID)
+                .and_then(|id| id.to_str().ok())
+                .and_then(|id| id.parse::<usize>().ok());
+            let auth_token = auth_token_param(&req);
             let (tx, rx) = oneshot::channel();
-            session.send(Msg::Subscribe(tx)).await?;
-            let rx = rx.await?;
+            session
+                .send(Msg::Subscribe(auth_token.clone(), last_event_id, tx))
+                .await?;
+            let sub = rx.await??;
+            // The user counts as present until the stream is dropped.
+            let presence = Presence::new(session, auth_token, sub.stream_id);
+            let replay = sub.replay.into_iter().map(|p| p.to_message());
+            let head = std::iter::once(event::retry_message(SSE_RETRY)).chain(replay);
+            // The stream ends once the auth token is revoked.
+            let revoked = futures::stream::once(sub.revoked).map(|_| None);
+            let events = sub
+                .rx
+                .map(Some)
+                .merge(revoked)
+                .take_while(Option::is_some)
+                .filter_map(move |ev| {
+                    let _ = &presence;
+                    ev.map(|ev| ev.map(|p| p.to_message()))
+                });
+            let rx = stream::iter(head.map(Ok)).chain(events);
             Ok(event_stream_builder().body(Body::wrap_stream(rx)).unwrap())
         }
+        (["ws"], &Method::GET) => ws::upgrade(session, req).await,
         _ => Err(Error::InvalidResource),
     }
 }
+
+/// Returns the auth token given in the query of the request, if there is one.
+fn auth_token_param(req: &Request) -> Option<AuthToken> {
+    let token = query_param(req, AUTH_TOKEN)?;
+    serde_json::from_value(token.into()).ok()
+}
+
+/// Returns the value of the given query parameter. Values are not
+/// percent-decoded.
+fn query_param<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            Some((kv.next()?, kv.next().unwrap_or("")))
+        })
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
@@ -0,0 +1,192 @@
+// Copyright (C) 2020  Kerem Çakırer
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{error::Error, utils::to_error_json, Request, Result, StdResult};
+use crate::{
+    common::*,
+    session::{self, Msg, Presence, Subscription},
+};
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use hyper::{upgrade::Upgraded, Body, Response};
+use tokio::{
+    select,
+    sync::{broadcast::RecvError, mpsc, oneshot},
+};
+use tokio_tungstenite::{
+    tungstenite::{handshake::server::create_response, protocol::Role, Message},
+    WebSocketStream,
+};
+
+type WebSocket = WebSocketStream<Upgraded>;
+/// `Ack` receives the outcome of a request sent over the WebSocket.
+enum Ack {
+    Empty(oneshot::Receiver<session::Result<()>>),
+    Json(oneshot::Receiver<session::Result<Vec<u8>>>),
+}
+
+pub async fn upgrade(session: mpsc::Sender<Msg>, req: Request) -> Result {
+    let mut handshake = hyper::Request::builder()
+        .method(req.method())
+        .version(req.version())
+        .body(())
+        .unwrap();
+    *handshake.headers_mut() = req.headers().clone();
+    let resp = create_response(&handshake).or(Err(Error::InvalidRequest))?;
+    tokio::spawn(async move {
+        if let Ok(upgraded) = req.into_body().on_upgrade().await {
+            let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+            let _ = serve(session, ws).await;
+        }
+    });
+    let (parts, ()) = resp.into_parts();
+    Ok(Response::from_parts(parts, Body::empty()))
+}
+
+async fn serve(mut session: mpsc::Sender<Msg>, ws: WebSocket) -> StdResult<(), Error> {
+    let (mut sink, mut stream) = ws.split();
+    let auth_token = match stream.next().await {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str::<req::Join>(&text)? {
+            req::Join::Connect { auth_token } => auth_token,
+            _ => return Err(Error::InvalidRequest),
+        },
+        _ => return Err(Error::InvalidRequest),
+    };
+    let (tx, rx) = oneshot::channel();
+    session
+        .send(Msg::J(
+            req::Join::Connect {
+                auth_token: auth_token.clone(),
+            },
+            tx,
+        ))
+        .await?;
+    match rx.await? {
+        Ok(json) => sink.send(to_text(json)).await?,
+        Err(err) => {
+            sink.send(to_error(err)).await?;
+            let _ = sink.close().await;
+            return Err(err.into());
+        }
+    }
+    let (mut sub, mut last_event_id) =
+        subscribe(&mut session, &auth_token, None, &mut sink).await?;
+    let mut _presence = Presence::new(session.clone(), Some(auth_token.clone()), sub.stream_id);
+    loop {
+        select! {
+            frame = stream.next() => match frame {
+                Some(Ok(Message::Text(text))) => match parse_frame(&auth_token, &text) {
+                    Ok((msg, ack)) => {
+                        session.send(msg).await?;
+                        let res = match ack {
+                            Ack::Empty(rx) => rx.await?.map(|()| None),
+                            Ack::Json(rx) => rx.await?.map(Some),
+                        };
+                        match res {
+                            Ok(Some(json)) => sink.send(to_text(json)).await?,
+                            Ok(None) => (),
+                            Err(err) => sink.send(to_error(err)).await?,
+                        }
+                    }
+                    Err(_) => {
+                        let json = to_error_json("invalidRequest", "The frame is malformed.");
+                        sink.send(to_text(json)).await?;
+                    }
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => (),
+            },
+            ev = sub.rx.recv() => match ev {
+                Ok(payload) => {
+                    last_event_id = payload.id;
+                    sink.send(to_text(payload.json)).await?;
+                }
+                Err(RecvError::Lagged(_)) => {
+                    // Catch up on the missed events, or get a snapshot if they
+                    // are gone. The new subscription counts as a connection of
+                    // its own, so the old presence is dropped.
+                    let (s, id) = subscribe(&mut session, &auth_token, Some(last_event_id), &mut sink).await?;
+                    sub = s;
+                    last_event_id = id;
+                    _presence = Presence::new(session.clone(), Some(auth_token.clone()), sub.stream_id);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // The user left or was kicked.
+            _ = &mut sub.revoked => break,
+        }
+    }
+    let _ = sink.close().await;
+    Ok(())
+}
+
+/// Subscribes to the events of the session and sends the ones the client
+/// missed since `last_event_id`, or a snapshot if there is no such ID. Returns
+/// the subscription for upcoming events along with the ID of the last event
+/// sent.
+async fn subscribe(
+    session: &mut mpsc::Sender<Msg>,
+    auth_token: &AuthToken,
+    last_event_id: Option<usize>,
+    sink: &mut SplitSink<WebSocket, Message>,
+) -> StdResult<(Subscription, usize), Error> {
+    let (tx, rx) = oneshot::channel();
+    session
+        .send(Msg::Subscribe(Some(auth_token.clone()), last_event_id, tx))
+        .await?;
+    let mut sub = rx.await??;
+    let mut last_event_id = last_event_id.unwrap_or(0);
+    for payload in sub.replay.drain(..) {
+        last_event_id = payload.id;
+        sink.send(to_text(payload.json)).await?;
+    }
+    Ok((sub, last_event_id))
+}
+
+/// Turns a frame into a message for the session, along with the receiver for
+/// its outcome.
+fn parse_frame(auth_token: &AuthToken, text: &str) -> serde_json::Result<(Msg, Ack)> {
+    let mut json = serde_json::from_str::<serde_json::Value>(text)?;
+    // Frames always act as the user the connection is bound to.
+    if let Some(obj) = json.as_object_mut() {
+        obj.insert("authToken".to_owned(), serde_json::to_value(auth_token)?);
+    }
+    let frame = serde_json::from_value::<req::Frame>(json)?;
+    let (empty_tx, empty_rx) = oneshot::channel();
+    let (json_tx, json_rx) = oneshot::channel();
+    let res = match frame {
+        req::Frame::Abort(a) => (Msg::Abort(a, empty_tx), Ack::Empty(empty_rx)),
+        req::Frame::Appoint(a) => (Msg::Appoint(a, empty_tx), Ack::Empty(empty_rx)),
+        req::Frame::Board(b) => (Msg::B(b, json_tx), Ack::Json(json_rx)),
+        req::Frame::Draw(d) => (Msg::Draw(d, json_tx), Ack::Json(json_rx)),
+        req::Frame::Kick(k) => (Msg::Kick(k, empty_tx), Ack::Empty(empty_rx)),
+        req::Frame::Leave(l) => (Msg::Leave(l, empty_tx), Ack::Empty(empty_rx)),
+        req::Frame::Match(m) => (Msg::Match(m, empty_tx), Ack::Empty(empty_rx)),
+        req::Frame::Participants(p) => (Msg::P(p, empty_tx), Ack::Empty(empty_rx)),
+        req::Frame::Queue(q) => (Msg::Queue(q, empty_tx), Ack::Empty(empty_rx)),
+        req::Frame::Resign(r) => (Msg::R(r, json_tx), Ack::Json(json_rx)),
+        req::Frame::Settings(st) => (Msg::Settings(st, empty_tx), Ack::Empty(empty_rx)),
+        req::Frame::Start(s) => (Msg::S(s, json_tx), Ack::Json(json_rx)),
+        req::Frame::Transfer(t) => (Msg::Transfer(t, empty_tx), Ack::Empty(empty_rx)),
+    };
+    Ok(res)
+}
+
+fn to_error(err: session::Error) -> Message {
+    to_text(to_error_json(err.code(), err.message()))
+}
+
+fn to_text(json: Vec<u8>) -> Message {
+    Message::Text(String::from_utf8(json).unwrap())
+}
@@ -14,18 +14,20 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use clap::{crate_name, crate_version, App, Arg};
-use config::Config;
+use config::{Config, SessionIdFormat};
 use dispatcher::dispatch;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
+use ratings::Ratings;
 use sessions::Sessions;
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, process, sync::Arc, time::Duration};
 use tokio::runtime;
 
 mod common;
 mod config;
 mod data;
 mod dispatcher;
+mod ratings;
 mod session;
 mod sessions;
 
@@ -56,6 +58,72 @@ fn parse_args() -> Config {
                 .takes_value(true)
                 .value_name("ADDR"),
         )
+        .arg(
+            Arg::with_name("max-sessions")
+                .long("max-sessions")
+                .takes_value(true)
+                .value_name("NUM")
+                .validator(validate_num),
+        )
+        .arg(
+            Arg::with_name("session-id-length")
+                .long("session-id-length")
+                .takes_value(true)
+                .value_name("NUM")
+                .validator(validate_session_id_length),
+        )
+        .arg(
+            Arg::with_name("session-id-alphabet")
+                .long("session-id-alphabet")
+                .takes_value(true)
+                .value_name("CHARS")
+                .validator(validate_session_id_alphabet),
+        )
+        .arg(
+            Arg::with_name("session-id-words")
+                .long("session-id-words")
+                .conflicts_with_all(&["session-id-length", "session-id-alphabet"]),
+        )
+        .arg(
+            Arg::with_name("lobby-timeout")
+                .long("lobby-timeout")
+                .takes_value(true)
+                .value_name("SECS")
+                .validator(validate_secs),
+        )
+        .arg(
+            Arg::with_name("game-timeout")
+                .long("game-timeout")
+                .takes_value(true)
+                .value_name("SECS")
+                .validator(validate_secs),
+        )
+        .arg(
+            Arg::with_name("expiry-warning")
+                .long("expiry-warning")
+                .takes_value(true)
+                .value_name("SECS")
+                .validator(validate_secs),
+        )
+        .arg(
+            Arg::with_name("owner-timeout")
+                .long("owner-timeout")
+                .takes_value(true)
+                .value_name("SECS")
+                .validator(validate_secs),
+        )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .takes_value(true)
+                .value_name("DIR"),
+        )
+        .arg(
+            Arg::with_name("ratings-file")
+                .long("ratings-file")
+                .takes_value(true)
+                .value_name("FILE"),
+        )
         .get_matches();
     if args.is_present("debug") {
         builder.debug(true);
@@ -66,9 +134,77 @@ fn parse_args() -> Config {
     if let Some(addr) = args.value_of("bind") {
         builder.bind_addr(addr.parse::<SocketAddr>().unwrap());
     }
+    if let Some(num) = args.value_of("max-sessions") {
+        builder.max_session(num.parse::<usize>().unwrap());
+    }
+    if args.is_present("session-id-words") {
+        builder.session_id_format(SessionIdFormat::Words);
+    } else if args.is_present("session-id-length") || args.is_present("session-id-alphabet") {
+        let len = args
+            .value_of("session-id-length")
+            .map_or(4, |num| num.parse::<usize>().unwrap());
+        let format = match args.value_of("session-id-alphabet") {
+            Some(chars) => SessionIdFormat::Chars {
+                alphabet: chars.chars().collect(),
+                len,
+            },
+            None => SessionIdFormat::alphanumeric(len),
+        };
+        builder.session_id_format(format);
+    }
+    if let Some(secs) = args.value_of("lobby-timeout") {
+        builder.lobby_timeout(Duration::from_secs(secs.parse::<u64>().unwrap()));
+    }
+    if let Some(secs) = args.value_of("game-timeout") {
+        builder.game_timeout(Duration::from_secs(secs.parse::<u64>().unwrap()));
+    }
+    if let Some(secs) = args.value_of("expiry-warning") {
+        builder.expiry_warning(Duration::from_secs(secs.parse::<u64>().unwrap()));
+    }
+    if let Some(secs) = args.value_of("owner-timeout") {
+        builder.owner_timeout(Duration::from_secs(secs.parse::<u64>().unwrap()));
+    }
+    if let Some(dir) = args.value_of("data-dir") {
+        builder.data_dir(dir);
+    }
+    if let Some(file) = args.value_of("ratings-file") {
+        builder.ratings_file(file);
+    }
     builder.build()
 }
 
+fn validate_num(num: String) -> Result<(), String> {
+    num.parse::<usize>()
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn validate_secs(secs: String) -> Result<(), String> {
+    secs.parse::<u64>()
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn validate_session_id_length(num: String) -> Result<(), String> {
+    match num.parse::<usize>() {
+        Ok(0) => Err("session ID length must be positive".to_owned()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Session IDs appear in URLs and file names, so we only allow alphanumeric
+/// characters.
+fn validate_session_id_alphabet(chars: String) -> Result<(), String> {
+    if chars.is_empty() {
+        Err("session ID alphabet must not be empty".to_owned())
+    } else if !chars.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Err("session ID alphabet must be alphanumeric".to_owned())
+    } else {
+        Ok(())
+    }
+}
+
 fn main() {
     let config = Arc::new(parse_args());
     debug!(config, "Current configuration: {:?}", config);
@@ -80,10 +216,20 @@ fn main() {
     }
     let mut rt = rt.enable_all().build().unwrap();
     debug!(config, "Using Tokio runtime: {:?}", rt);
-    let sessions = Sessions::new(config.clone());
+    let ratings = match Ratings::load(config.ratings_file()) {
+        Ok(ratings) => ratings,
+        Err(err) => {
+            eprintln!("Failed to load the ratings: {}", err);
+            process::exit(1);
+        }
+    };
+    let sessions = Sessions::new(config.clone(), ratings);
     let make_svc = make_service_fn(|_| {
         let sessions = sessions.clone();
         async { Ok::<_, hyper::Error>(service_fn(move |req| dispatch(sessions.clone(), req))) }
     });
-    let _ = rt.block_on(async { Server::bind(config.bind_addr()).serve(make_svc).await });
+    let _ = rt.block_on(async {
+        sessions.restore().await;
+        Server::bind(config.bind_addr()).serve(make_svc).await
+    });
 }
@@ -0,0 +1,331 @@
+// Copyright (C) 2020  Kerem Çakırer
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::common::{resp, save_file, PlayerToken};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Converts between the Glicko and the Glicko-2 scale.
+const SCALE: f64 = 173.7178;
+/// Constrains how fast the volatility changes.
+const TAU: f64 = 0.5;
+const EPSILON: f64 = 0.000001;
+
+/// A player's token along with the name they play under.
+pub type Entry<'a> = (&'a PlayerToken, &'a str);
+
+/// `Rating` is a Glicko-2 rating, given on the Glicko scale.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rating {
+    pub rating: f64,
+    /// How uncertain the rating is. Ratings of players who played few games
+    /// have a high deviation.
+    pub deviation: f64,
+    /// How erratic the results are.
+    pub volatility: f64,
+    pub games: usize,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: 1500.0,
+            deviation: 350.0,
+            volatility: 0.06,
+            games: 0,
+        }
+    }
+}
+
+impl Rating {
+    /// Returns the rating after a rating period with the given results. Each
+    /// result is an opponent's rating and the score against them: 1 for a
+    /// win, 0.5 for a draw and 0 for a loss.
+    fn update(&self, results: &[(Rating, f64)]) -> Self {
+        let mu = (self.rating - 1500.0) / SCALE;
+        let phi = self.deviation / SCALE;
+        let sigma = self.volatility;
+        let g = |phi: f64| 1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt();
+        let (mut v_inv, mut sum) = (0.0, 0.0);
+        for (opponent, score) in results {
+            let mu_j = (opponent.rating - 1500.0) / SCALE;
+            let g_j = g(opponent.deviation / SCALE);
+            let e = 1.0 / (1.0 + (-g_j * (mu - mu_j)).exp());
+            v_inv += g_j * g_j * e * (1.0 - e);
+            sum += g_j * (score - e);
+        }
+        let v = 1.0 / v_inv;
+        let delta = v * sum;
+        // Find the new volatility with the Illinois algorithm.
+        let a = (sigma * sigma).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+        };
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let (mut f_a, mut f_b) = (f(big_a), f(big_b));
+        while (big_b - big_a).abs() > EPSILON {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+            big_b = big_c;
+            f_b = f_c;
+        }
+        let volatility = (big_a / 2.0).exp();
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * sum;
+        Self {
+            rating: SCALE * mu + 1500.0,
+            deviation: SCALE * phi,
+            volatility,
+            games: self.games + 1,
+        }
+    }
+}
+
+/// `Player` is the rating of a player, under the name they last played with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Player {
+    pub name: String,
+    #[serde(flatten)]
+    pub rating: Rating,
+}
+
+/// `Team` is the rating of two players who played a match together.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Team {
+    pub names: (String, String),
+    #[serde(flatten)]
+    pub rating: Rating,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Store {
+    /// Players by their player token.
+    players: HashMap<PlayerToken, Player>,
+    /// Teams by the player tokens of their players, in sorted order and
+    /// joined with a `+`.
+    teams: HashMap<String, Team>,
+    /// Number of games recorded since the ratings were loaded.
+    #[serde(skip)]
+    changes: usize,
+}
+
+/// `Ratings` holds the ratings of every player who finished a rated game,
+/// and of every team which played a match. Players are told apart by their
+/// player tokens, so that nobody can play under someone else's rating by
+/// taking their name.
+#[derive(Clone)]
+pub struct Ratings {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    path: Option<PathBuf>,
+    store: Mutex<Store>,
+    /// The number of changes which were last written to the file.
+    written: tokio::sync::Mutex<usize>,
+}
+
+impl Ratings {
+    /// Reads the ratings from the file at `path`, if there is one. Fails if
+    /// the file exists but can't be read, so that it isn't overwritten.
+    pub fn load(path: Option<PathBuf>) -> io::Result<Self> {
+        let store = match path.as_ref().map(fs::read) {
+            Some(Ok(json)) => serde_json::from_slice(&json)?,
+            Some(Err(err)) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => Store::default(),
+        };
+        Ok(Self {
+            inner: Arc::new(Inner {
+                path,
+                store: Mutex::new(store),
+                written: tokio::sync::Mutex::new(0),
+            }),
+        })
+    }
+
+    /// Updates the ratings after a game between two teams, written as
+    /// ((white, black), (black, white)). `score` is the first team's score:
+    /// 1 for a win, 0.5 for a draw and 0 for a loss. If `fixed` is `true`, the
+    /// teams are rated too.
+    pub fn record(&self, players: ((Entry, Entry), (Entry, Entry)), score: f64, fixed: bool) {
+        let ((a, b), (c, d)) = players;
+        let mut store = self.inner.store.lock().unwrap();
+        let teams = [[a, b], [c, d]];
+        let scores = [score, 1.0 - score];
+        let before = |store: &Store, token: &PlayerToken| {
+            store
+                .players
+                .get(token)
+                .map_or_else(Rating::default, |p| p.rating)
+        };
+        let mut after = Vec::with_capacity(4);
+        for (i, team) in teams.iter().enumerate() {
+            let opponents = &teams[1 - i];
+            for &(token, name) in team {
+                let results: Vec<_> = opponents
+                    .iter()
+                    .map(|&(o, _)| (before(&store, o), scores[i]))
+                    .collect();
+                after.push((token, name, before(&store, token).update(&results)));
+            }
+        }
+        for (token, name, rating) in after {
+            let player = Player {
+                name: name.trim().to_owned(),
+                rating,
+            };
+            store.players.insert(token.clone(), player);
+        }
+        if fixed {
+            let keys = [team_key(a.0, b.0), team_key(c.0, d.0)];
+            let before = |store: &Store, key: &str| {
+                store
+                    .teams
+                    .get(key)
+                    .map_or_else(Rating::default, |t| t.rating)
+            };
+            let ratings = [before(&store, &keys[0]), before(&store, &keys[1])];
+            for i in 0..2 {
+                let team = Team {
+                    names: (
+                        teams[i][0].1.trim().to_owned(),
+                        teams[i][1].1.trim().to_owned(),
+                    ),
+                    rating: ratings[i].update(&[(ratings[1 - i], scores[i])]),
+                };
+                store.teams.insert(keys[i].clone(), team);
+            }
+        }
+        store.changes += 1;
+    }
+
+    /// Writes the ratings to their file, unless a newer state was already
+    /// written.
+    pub async fn save(&self) {
+        let path = match &self.inner.path {
+            Some(path) => path,
+            None => return,
+        };
+        let (changes, json) = {
+            let store = self.inner.store.lock().unwrap();
+            (store.changes, serde_json::to_vec(&*store).unwrap())
+        };
+        save_file(path, json, changes, &self.inner.written).await;
+    }
+
+    /// Returns every player and team, the best rated first.
+    pub fn leaderboard(&self) -> resp::Ratings {
+        let store = self.inner.store.lock().unwrap();
+        let mut players: Vec<_> = store.players.values().cloned().collect();
+        let mut teams: Vec<_> = store.teams.values().cloned().collect();
+        players.sort_by(|p, q| q.rating.rating.total_cmp(&p.rating.rating));
+        teams.sort_by(|t, u| u.rating.rating.total_cmp(&t.rating.rating));
+        resp::Ratings { players, teams }
+    }
+}
+
+/// Returns the key of the team of the two players, whichever seats they take.
+fn team_key(a: &PlayerToken, b: &PlayerToken) -> String {
+    if a <= b {
+        format!("{}+{}", a, b)
+    } else {
+        format!("{}+{}", b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            ..Rating::default()
+        }
+    }
+
+    #[test]
+    fn update_matches_glickmans_example() {
+        // The example from "Example of the Glicko-2 system" by Glickman.
+        let player = rating(1500.0, 200.0);
+        let results = [
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ];
+        let after = player.update(&results);
+        assert!((after.rating - 1464.05).abs() < 0.01, "{}", after.rating);
+        assert!(
+            (after.deviation - 151.52).abs() < 0.01,
+            "{}",
+            after.deviation
+        );
+        assert!(
+            (after.volatility - 0.05999).abs() < 0.00001,
+            "{}",
+            after.volatility
+        );
+        assert_eq!(after.games, 1);
+    }
+
+    #[test]
+    fn team_keys_ignore_seats() {
+        let (a, b, c) = (PlayerToken::new(), PlayerToken::new(), PlayerToken::new());
+        assert_eq!(team_key(&a, &b), team_key(&b, &a));
+        assert_ne!(team_key(&a, &b), team_key(&a, &c));
+    }
+
+    #[test]
+    fn players_are_told_apart_by_token() {
+        let ratings = Ratings::load(None).unwrap();
+        let tokens: Vec<_> = (0..4).map(|_| PlayerToken::new()).collect();
+        let e = |i: usize| (&tokens[i], "Same Name");
+        ratings.record(((e(0), e(1)), (e(2), e(3))), 1.0, true);
+        let board = ratings.leaderboard();
+        assert_eq!(board.players.len(), 4);
+        assert_eq!(board.teams.len(), 2);
+        assert!(board.players[1].rating.rating > board.players[2].rating.rating);
+        assert!(board.teams[0].rating.rating > board.teams[1].rating.rating);
+    }
+}
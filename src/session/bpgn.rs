@@ -0,0 +1,288 @@
+// Copyright (C) 2020  Kerem Çakırer
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{san, EndReason, Game, GameResult, Move, Session};
+use crate::data::{Delay, TimeControl, UserId};
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Returns the game with the given ID in the bughouse portable game notation.
+pub fn export(s: &Session, id: usize) -> Option<String> {
+    let game = s.find_game(id)?;
+    let result = s.archived(id).map(|(_, result)| result);
+    let name = |user_id: &UserId| {
+        s.users
+            .get(user_id)
+            .map(|u| u.name().to_owned())
+            .unwrap_or_else(|| "?".to_owned())
+    };
+    let ((a, b), (c, d)) = &game.active_participants;
+    let mut out = String::new();
+    let _ = writeln!(out, "[Event \"Bughouse session {}\"]", s.id);
+    let _ = writeln!(out, "[Site \"?\"]");
+    let _ = writeln!(out, "[Date \"{}\"]", date(game.date));
+    let _ = writeln!(out, "[Round \"{}\"]", id);
+    let _ = writeln!(out, "[WhiteA \"{}\"]", name(a));
+    let _ = writeln!(out, "[BlackA \"{}\"]", name(c));
+    let _ = writeln!(out, "[WhiteB \"{}\"]", name(d));
+    let _ = writeln!(out, "[BlackB \"{}\"]", name(b));
+    let _ = writeln!(
+        out,
+        "[TimeControl \"{}\"]",
+        time_control(&game.time_control)
+    );
+    let _ = writeln!(out, "[Result \"{}\"]", result_token(game, result));
+    if let Some(result) = result {
+        let _ = writeln!(out, "[Termination \"{}\"]", termination(result));
+    }
+    let _ = writeln!(out);
+    let mate = result
+        .filter(|r| matches!(r.reason, EndReason::Checkmate))
+        .and_then(|r| r.board);
+    let mut movetext = moves(&game.moves, mate);
+    if !movetext.is_empty() {
+        movetext.push(' ');
+    }
+    let _ = writeln!(out, "{}{}", movetext, result_token(game, result));
+    Some(out)
+}
+
+/// Boards 1 and 2 are written as A and B. Moves are numbered separately for
+/// each board, using upper case letters for white and lower case letters for
+/// black, e.g. `1A. e4 1B. d4 1a. e5`. The last move on the board given by
+/// `mate` is marked as checkmate.
+///
+/// The moves are written in SAN, with drops as e.g. `N@f3`. If a board can't
+/// be followed, its remaining moves are written as they were made, e.g.
+/// `e2e4`.
+fn moves(moves: &[Move], mate: Option<u8>) -> String {
+    // Number of moves made so far by white and black on board A and B.
+    let mut counts = [[0; 2]; 2];
+    let mut boards = [Some(san::Board::new()), Some(san::Board::new())];
+    let mating = mate.and_then(|board| moves.iter().rposition(|m| m.board == board));
+    moves
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let count = &mut counts[(m.board - 1) as usize][if m.white { 0 } else { 1 }];
+            *count += 1;
+            let board = &mut boards[(m.board - 1) as usize];
+            let mut notation = match board.as_mut().and_then(|b| b.play(&m.kind, m.white)) {
+                Some(san) => san,
+                None => {
+                    *board = None;
+                    m.notation.clone()
+                }
+            };
+            if Some(i) == mating {
+                if notation.ends_with('+') {
+                    notation.pop();
+                }
+                notation.push('#');
+            }
+            let board = match (m.board, m.white) {
+                (1, true) => 'A',
+                (1, false) => 'a',
+                (_, true) => 'B',
+                (_, false) => 'b',
+            };
+            let (rw, rb) = m.remaining_time;
+            let remaining = if m.white { rw } else { rb };
+            format!(
+                "{}{}. {} {{[%clk {}]}}",
+                count,
+                board,
+                notation,
+                clock(remaining)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn result_token(game: &Game, result: Option<&GameResult>) -> &'static str {
+    let (team, _) = game.active_participants;
+    match result.map(|r| (r.winners, r.reason)) {
+        Some((Some(winners), _)) if winners == team => "1-0",
+        Some((Some(_), _)) => "0-1",
+        Some((None, EndReason::Draw)) | Some((None, EndReason::DrawAgreement)) => "1/2-1/2",
+        _ => "*",
+    }
+}
+
+fn termination(result: &GameResult) -> &'static str {
+    match result.reason {
+        EndReason::Flag => "time forfeit",
+        EndReason::Abort | EndReason::Termination => "abandoned",
+        _ => "normal",
+    }
+}
+
+/// Formats the time control as `base+increment` in seconds. A delay is
+/// appended as `d` for a simple delay or `b` for a Bronstein delay followed by
+/// its seconds, e.g. `300+0d5`.
+fn time_control(tc: &TimeControl) -> String {
+    let mut out = format!("{}+{}", secs(tc.base), secs(tc.increment));
+    match tc.delay {
+        Delay::None => (),
+        Delay::Bronstein { delay } => out += &format!("b{}", secs(delay)),
+        Delay::Simple { delay } => out += &format!("d{}", secs(delay)),
+    }
+    out
+}
+
+/// Formats `d` in seconds, with a fraction only if it has one.
+fn secs(d: Duration) -> String {
+    if d.subsec_millis() == 0 {
+        d.as_secs().to_string()
+    } else {
+        format!("{}", d.as_secs_f64())
+    }
+}
+
+fn clock(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    format!(
+        "{}:{:02}:{:02}.{}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        remaining.subsec_millis() / 100
+    )
+}
+
+/// Formats the date part of `time` as `YYYY.MM.DD`.
+fn date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0);
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::MoveKind;
+
+    fn mv(board: u8, white: bool, change: &str, remaining: u64) -> Move {
+        let remaining = Duration::from_secs(remaining);
+        Move {
+            board,
+            white,
+            user_id: UserId::new(1),
+            notation: change.to_owned(),
+            kind: MoveKind::Move {
+                change: change.to_owned(),
+            },
+            remaining_time: (remaining, remaining),
+            timestamp: Duration::from_secs(0),
+        }
+    }
+
+    #[test]
+    fn moves_are_numbered_per_board_and_color() {
+        let drop = Move {
+            kind: MoveKind::Deploy {
+                piece: "Q".to_owned(),
+                pos: "d5".to_owned(),
+            },
+            ..mv(1, true, "Q@d5", 296)
+        };
+        let ms = [
+            mv(1, true, "e2e4", 299),
+            mv(2, true, "d2d4", 298),
+            mv(1, false, "e7e5", 297),
+            drop,
+        ];
+        assert_eq!(
+            moves(&ms, None),
+            "1A. e4 {[%clk 0:04:59.0]} 1B. d4 {[%clk 0:04:58.0]} \
+             1a. e5 {[%clk 0:04:57.0]} 2A. Q@d5 {[%clk 0:04:56.0]}"
+        );
+        assert_eq!(moves(&[], None), "");
+    }
+
+    #[test]
+    fn mates_are_marked_on_their_board() {
+        let ms = [
+            mv(1, true, "f2f3", 60),
+            mv(1, false, "e7e5", 60),
+            mv(2, true, "e2e4", 60),
+            mv(1, true, "g2g4", 60),
+            mv(1, false, "d8h4", 60),
+            mv(2, false, "e7e5", 60),
+        ];
+        let text = moves(&ms, Some(1));
+        assert!(text.contains("2a. Qh4# "), "{}", text);
+        assert!(text.ends_with("1b. e5 {[%clk 0:01:00.0]}"), "{}", text);
+    }
+
+    #[test]
+    fn boards_which_cant_be_followed_keep_their_moves() {
+        let ms = [mv(1, true, "e2e5", 60), mv(1, false, "e7e5", 60)];
+        assert_eq!(
+            moves(&ms, None),
+            "1A. e2e5 {[%clk 0:01:00.0]} 1a. e7e5 {[%clk 0:01:00.0]}"
+        );
+    }
+
+    #[test]
+    fn clocks_show_tenths_of_seconds() {
+        assert_eq!(clock(Duration::from_millis(0)), "0:00:00.0");
+        assert_eq!(clock(Duration::from_millis(61_999)), "0:01:01.9");
+        assert_eq!(clock(Duration::from_secs(3 * 3600 + 5)), "3:00:05.0");
+    }
+
+    #[test]
+    fn time_controls_include_the_delay() {
+        let mut tc = TimeControl {
+            base: Duration::from_secs(300),
+            increment: Duration::from_secs(2),
+            delay: Delay::None,
+            promotion_bonus: Duration::from_secs(0),
+        };
+        assert_eq!(time_control(&tc), "300+2");
+        tc.delay = Delay::Simple {
+            delay: Duration::from_secs(5),
+        };
+        assert_eq!(time_control(&tc), "300+2d5");
+        tc.delay = Delay::Bronstein {
+            delay: Duration::from_millis(1500),
+        };
+        assert_eq!(time_control(&tc), "300+2b1.5");
+    }
+
+    #[test]
+    fn dates_are_civil_dates() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(date(at(0)), "1970.01.01");
+        assert_eq!(date(at(951_782_400)), "2000.02.29");
+        assert_eq!(date(at(1_609_459_199)), "2020.12.31");
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (C) 2020  Kerem Çakırer
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// `Error` is the reason why a session rejected a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The user name is empty or too long.
+    InvalidName,
+    /// The player token is too short or has characters other than letters
+    /// and digits.
+    InvalidPlayerToken,
+    /// The time control has no base time, or a part of it is longer than a day.
+    InvalidTimeControl,
+    /// The countdown before the next game starts on its own is too long.
+    InvalidAutoStart,
+    /// The session has reached its user limit.
+    SessionFull,
+    /// The session has reached its spectator limit.
+    SpectatorsFull,
+    /// The session doesn't allow spectators without a name.
+    AnonymousSpectator,
+    /// The auth token doesn't belong to any user of the session.
+    Unauthorized,
+    /// Only the owner of the session may do this.
+    NotOwner,
+    /// Only the owner or a moderator of the session may do this.
+    NotModerator,
+    /// The role can't be given to the user, or the user's role protects them
+    /// from the action.
+    InvalidRole,
+    /// Spectators can't be chosen as participants.
+    NotPlayer,
+    /// The user was banned from the session.
+    Banned,
+    /// The owner can't leave or be kicked from the session.
+    OwnerCannotLeave,
+    /// The user doesn't play in the current game.
+    NotParticipant,
+    /// It is the other player's turn on the user's board.
+    NotYourTurn,
+    /// The player's time ran out before they moved.
+    OutOfTime,
+    /// The piece, square or move couldn't be parsed.
+    InvalidNotation,
+    /// The move is not legal in the current position.
+    IllegalMove,
+    /// The board has changed since the user saw it.
+    StalePosition,
+    /// The other team hasn't offered a draw.
+    NoDrawOffer,
+    /// There is no game in progress.
+    GameNotRunning,
+    /// A game is already in progress.
+    GameRunning,
+    /// The game is paused until every player reconnects.
+    GamePaused,
+    /// Some of the participants are not users of the session.
+    UnknownUser,
+    /// There are too few or too many participants to start a game.
+    InvalidParticipantCount,
+    /// A game needs four different players.
+    InvalidPairing,
+    /// There is no game at the given position in the queue.
+    InvalidQueuePosition,
+    /// A series needs an odd number of games, and at most 99.
+    InvalidSeriesLength,
+    /// No pairing is left to play.
+    NoPairings,
+}
+
+impl Error {
+    /// Returns a stable identifier which clients can match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidName => "invalidName",
+            Self::InvalidPlayerToken => "invalidPlayerToken",
+            Self::InvalidTimeControl => "invalidTimeControl",
+            Self::InvalidAutoStart => "invalidAutoStart",
+            Self::SessionFull => "sessionFull",
+            Self::SpectatorsFull => "spectatorsFull",
+            Self::AnonymousSpectator => "anonymousSpectator",
+            Self::Unauthorized => "unauthorized",
+            Self::NotOwner => "notOwner",
+            Self::NotModerator => "notModerator",
+            Self::InvalidRole => "invalidRole",
+            Self::NotPlayer => "notPlayer",
+            Self::Banned => "banned",
+            Self::OwnerCannotLeave => "ownerCannotLeave",
+            Self::NotParticipant => "notParticipant",
+            Self::NotYourTurn => "notYourTurn",
+            Self::OutOfTime => "outOfTime",
+            Self::InvalidNotation => "invalidNotation",
+            Self::IllegalMove => "illegalMove",
+            Self::StalePosition => "stalePosition",
+            Self::NoDrawOffer => "noDrawOffer",
+            Self::GameNotRunning => "gameNotRunning",
+            Self::GameRunning => "gameRunning",
+            Self::GamePaused => "gamePaused",
+            Self::UnknownUser => "unknownUser",
+            Self::InvalidParticipantCount => "invalidParticipantCount",
+            Self::InvalidPairing => "invalidPairing",
+            Self::InvalidQueuePosition => "invalidQueuePosition",
+            Self::InvalidSeriesLength => "invalidSeriesLength",
+            Self::NoPairings => "noPairings",
+        }
+    }
+
+    /// Returns a message which can be shown to the user.
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::InvalidName => "The user name is not valid.",
+            Self::InvalidPlayerToken => "The player token is not valid.",
+            Self::InvalidTimeControl => {
+                "The time control must have a base time and no part of it may exceed 24 hours."
+            }
+            Self::InvalidAutoStart => "The next game can start on its own after at most an hour.",
+            Self::SessionFull => "The session has no room for more users.",
+            Self::SpectatorsFull => "The session has no room for more spectators.",
+            Self::AnonymousSpectator => "The session doesn't allow anonymous spectators.",
+            Self::Unauthorized => "The auth token is not valid for this session.",
+            Self::NotOwner => "Only the owner of the session can do this.",
+            Self::NotModerator => "Only the owner or a moderator can do this.",
+            Self::InvalidRole => "The action isn't possible for the user's role.",
+            Self::NotPlayer => "Spectators can't be chosen as participants.",
+            Self::Banned => "You are banned from this session.",
+            Self::OwnerCannotLeave => "The owner can't leave the session.",
+            Self::NotParticipant => "You are not playing in the current game.",
+            Self::NotYourTurn => "It is not your turn.",
+            Self::OutOfTime => "Your time has run out.",
+            Self::InvalidNotation => "The move could not be parsed.",
+            Self::IllegalMove => "The move is not legal.",
+            Self::StalePosition => "The board has changed since your last update.",
+            Self::NoDrawOffer => "The other team hasn't offered a draw.",
+            Self::GameNotRunning => "There is no game in progress.",
+            Self::GameRunning => "A game is already in progress.",
+            Self::GamePaused => "The game is paused until every player reconnects.",
+            Self::UnknownUser => "The user is not in the session.",
+            Self::InvalidParticipantCount => "The number of participants is not valid.",
+            Self::InvalidPairing => "A game needs four different players.",
+            Self::InvalidQueuePosition => "There is no game at this position in the queue.",
+            Self::InvalidSeriesLength => "A series needs an odd number of games, and at most 99.",
+            Self::NoPairings => "There are no pairings left to play.",
+        }
+    }
+}
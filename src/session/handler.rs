@@ -13,143 +13,457 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::Session;
+use super::{bpgn, utils, CloseReason, EndReason, Error, GameResult, Session};
 use crate::{
-    common::{event::EventType, req::*, resp::*, *},
-    data::UserId,
+    common::{
+        event::{EventType, Payload},
+        req::*,
+        resp::*,
+        *,
+    },
+    data::{Role, UserId},
 };
-use tokio::sync::{broadcast, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot};
 
-type Result = std::result::Result<(), ()>;
+type Result<T = ()> = super::Result<T>;
 
 pub enum Msg {
-    C(Create, oneshot::Sender<Vec<u8>>),
-    D(Delete),
-    J(Join, oneshot::Sender<Vec<u8>>),
-    S(Start),
-    R(Resign),
-    B(Board),
-    P(Participants),
-    Subscribe(oneshot::Sender<broadcast::Receiver<Vec<u8>>>),
+    C(Create, oneshot::Sender<Result<Vec<u8>>>),
+    D(Delete, oneshot::Sender<Result>),
+    J(Join, oneshot::Sender<Result<Vec<u8>>>),
+    Spectate(Spectate, oneshot::Sender<Result<Vec<u8>>>),
+    S(Start, oneshot::Sender<Result<Vec<u8>>>),
+    R(Resign, oneshot::Sender<Result<Vec<u8>>>),
+    B(Board, oneshot::Sender<Result<Vec<u8>>>),
+    Draw(Draw, oneshot::Sender<Result<Vec<u8>>>),
+    P(Participants, oneshot::Sender<Result>),
+    Queue(Queue, oneshot::Sender<Result>),
+    Match(Match, oneshot::Sender<Result>),
+    Abort(Abort, oneshot::Sender<Result>),
+    Kick(Kick, oneshot::Sender<Result>),
+    Leave(Leave, oneshot::Sender<Result>),
+    Transfer(Transfer, oneshot::Sender<Result>),
+    Appoint(Appoint, oneshot::Sender<Result>),
+    Settings(Settings, oneshot::Sender<Result>),
+    Moves(usize, oneshot::Sender<Option<Vec<u8>>>),
+    Bpgn(usize, oneshot::Sender<Option<String>>),
+    Listing(oneshot::Sender<Option<Listing>>),
+    State(Option<AuthToken>, oneshot::Sender<Result<Vec<u8>>>),
+    Subscribe(
+        Option<AuthToken>,
+        Option<usize>,
+        oneshot::Sender<Result<Subscription>>,
+    ),
+    Disconnect(Option<AuthToken>, usize),
+}
+
+/// `Subscription` holds the events a subscriber has missed since the ID it
+/// last saw, followed by the receiver for upcoming events. New subscribers
+/// start with a snapshot of the current state. The stream should be closed
+/// once `revoked` resolves.
+pub struct Subscription {
+    pub stream_id: usize,
+    pub replay: Vec<Payload>,
+    pub rx: broadcast::Receiver<Payload>,
+    pub revoked: oneshot::Receiver<()>,
+}
+
+/// `Presence` tells the session that a user or a spectator closed an event
+/// stream when it is dropped. It should live as long as the stream opened with
+/// the auth token, or without one for anonymous spectators.
+pub struct Presence {
+    session: mpsc::Sender<Msg>,
+    auth_token: Option<AuthToken>,
+    stream_id: usize,
+}
+
+impl Presence {
+    pub fn new(
+        session: mpsc::Sender<Msg>,
+        auth_token: Option<AuthToken>,
+        stream_id: usize,
+    ) -> Self {
+        Self {
+            session,
+            auth_token,
+            stream_id,
+        }
+    }
+}
+
+impl Drop for Presence {
+    fn drop(&mut self) {
+        let mut session = self.session.clone();
+        let auth_token = self.auth_token.clone();
+        let stream_id = self.stream_id;
+        tokio::spawn(async move {
+            let _ = session.send(Msg::Disconnect(auth_token, stream_id)).await;
+        });
+    }
 }
 
 pub async fn handle_msg(s: &mut Session, msg: Msg) {
-    let _ = match msg {
-        Msg::C(c, tx) => handle_create(s, c, tx).await,
-        Msg::D(d) => handle_delete(s, d).await,
-        Msg::J(j, tx) => handle_join(s, j, tx).await,
-        Msg::S(st) => handle_start(s, st).await,
-        Msg::R(r) => handle_resign(s, r).await,
-        Msg::B(b) => handle_board(s, b).await,
-        Msg::P(p) => handle_participants(s, p).await,
-        Msg::Subscribe(tx) => handle_subscribe(s, tx).await,
+    // Only requests which may change the session keep it alive.
+    let is_action = !matches!(
+        msg,
+        Msg::Moves(..)
+            | Msg::Bpgn(..)
+            | Msg::Listing(..)
+            | Msg::State(..)
+            | Msg::Subscribe(..)
+            | Msg::Disconnect(..)
+    );
+    let res = match msg {
+        Msg::C(c, tx) => reply(tx, handle_create(s, c).await),
+        Msg::D(d, tx) => reply(tx, handle_delete(s, d).await),
+        Msg::J(j, tx) => reply(tx, handle_join(s, j).await),
+        Msg::Spectate(sp, tx) => reply(tx, handle_spectate(s, sp).await),
+        Msg::S(st, tx) => reply(tx, handle_start(s, st).await),
+        Msg::R(r, tx) => reply(tx, handle_resign(s, r).await),
+        Msg::B(b, tx) => reply(tx, handle_board(s, b).await),
+        Msg::Draw(d, tx) => reply(tx, handle_draw(s, d).await),
+        Msg::P(p, tx) => reply(tx, handle_participants(s, p).await),
+        Msg::Queue(q, tx) => reply(tx, handle_queue(s, q).await),
+        Msg::Match(m, tx) => reply(tx, handle_match(s, m).await),
+        Msg::Abort(a, tx) => reply(tx, handle_abort(s, a).await),
+        Msg::Kick(k, tx) => reply(tx, handle_kick(s, k).await),
+        Msg::Leave(l, tx) => reply(tx, handle_leave(s, l).await),
+        Msg::Transfer(t, tx) => reply(tx, handle_transfer(s, t).await),
+        Msg::Appoint(a, tx) => reply(tx, handle_appoint(s, a).await),
+        Msg::Settings(st, tx) => reply(tx, handle_settings(s, st).await),
+        Msg::Moves(id, tx) => handle_moves(s, id, tx).await,
+        Msg::Bpgn(id, tx) => handle_bpgn(s, id, tx).await,
+        Msg::Listing(tx) => handle_listing(s, tx).await,
+        Msg::State(auth_token, tx) => reply(tx, handle_state(s, auth_token).await),
+        Msg::Subscribe(auth_token, last_event_id, tx) => {
+            reply(tx, handle_subscribe(s, auth_token, last_event_id).await)
+        }
+        Msg::Disconnect(auth_token, stream_id) => handle_disconnect(s, auth_token, stream_id).await,
     };
+    if is_action && res.is_ok() {
+        s.touch();
+    }
+}
+
+/// Sends the outcome of a request back to the requester.
+fn reply<T>(tx: oneshot::Sender<Result<T>>, res: Result<T>) -> Result {
+    let status = res.as_ref().map(|_| ()).map_err(|err| *err);
+    let _ = tx.send(res);
+    status
+}
+
+/// Serializes the game with the given ID as it is right now.
+fn position(s: &Session, id: usize) -> Result<Vec<u8>> {
+    let game = s.find_game(id).ok_or(Error::GameNotRunning)?;
+    let result = s.archived(id).map(|(_, result)| result);
+    let json = serde_json::to_vec(&Position {
+        game_id: id,
+        game,
+        result,
+    })
+    .unwrap();
+    Ok(json)
 }
 
 pub fn handle_timer(s: &mut Session) {
     s.tick();
 }
 
+pub fn handle_next_game_timer(s: &mut Session) {
+    s.next_game_at = None;
+    if s.start_game().is_ok() {
+        s.notify_all(UserId::OWNER, EventType::GameStarted);
+    }
+}
+
+pub fn handle_idle_timer(s: &mut Session) {
+    s.check_expiry();
+}
+
 pub fn handle_broadcast_timer(s: &mut Session) {
+    s.check_owner_presence();
     s.notify_all(UserId::OWNER, EventType::Periodic);
 }
 
-async fn handle_create(s: &mut Session, req: Create, tx: oneshot::Sender<Vec<u8>>) -> Result {
-    let res = s.add_user(req.owner_name);
-    if res.is_err() {
-        s.rx.close();
-        return Err(());
+async fn handle_create(s: &mut Session, req: Create) -> Result<Vec<u8>> {
+    let time_control = req.time_control.unwrap_or_default();
+    let res = if !time_control.is_valid() {
+        Err(Error::InvalidTimeControl)
+    } else if !utils::is_valid_auto_start(req.auto_start) {
+        Err(Error::InvalidAutoStart)
+    } else {
+        s.add_user(req.owner_name, req.player_token)
+    };
+    let (user_id, auth_token, player_token) = res.inspect_err(|_| s.rx.close())?;
+    if let Some(u) = s.users.get_mut(&user_id) {
+        *u.role_mut() = Role::Owner;
     }
-    let (user_id, auth_token) = res.or(Err(()))?;
+    // The session is handed over if the owner never connects.
+    s.reset_owner_presence();
+    s.time_control = time_control;
+    s.public = req.public;
+    s.anonymous_spectators = req.anonymous_spectators.unwrap_or(true);
+    s.auto_start = req.auto_start;
+    s.dirty = true;
     let json = serde_json::to_vec(&Created {
         session_id: &s.id,
         auth_token: &auth_token,
+        player_token: &player_token,
     })
     .unwrap();
-    let _ = tx.send(json);
-    Ok(())
+    Ok(json)
 }
 
 async fn handle_delete(s: &mut Session, req: Delete) -> Result {
-    if !s.is_owner(&req.auth_token) {
-        return Err(());
-    }
-    s.rx.close();
+    s.check_role(&req.auth_token, Role::Owner)?;
+    s.end_game(GameResult::new(EndReason::Termination));
+    s.close(CloseReason::Deleted);
     Ok(())
 }
 
-async fn handle_join(s: &mut Session, req: Join, tx: oneshot::Sender<Vec<u8>>) -> Result {
+async fn handle_join(s: &mut Session, req: Join) -> Result<Vec<u8>> {
     match req {
-        Join::Join { user_name } => handle_join2(s, user_name, tx).await,
-        Join::Connect { auth_token } => handle_connect(s, auth_token, tx).await,
+        Join::Join {
+            user_name,
+            player_token,
+        } => handle_join2(s, user_name, player_token).await,
+        Join::Connect { auth_token } => handle_connect(s, auth_token).await,
     }
 }
 
-async fn handle_start(s: &mut Session, req: Start) -> Result {
-    if !s.is_owner(&req.auth_token) {
-        return Err(());
-    }
-    s.start_game().or(Err(()))?;
-    s.notify_all(UserId::OWNER, EventType::GameStarted);
-    Ok(())
+async fn handle_spectate(s: &mut Session, req: Spectate) -> Result<Vec<u8>> {
+    let auth_token = s.add_spectator(req.name)?;
+    s.dirty = true;
+    let json = serde_json::to_vec(&Joined {
+        auth_token: &auth_token,
+        player_token: None,
+    })
+    .unwrap();
+    Ok(json)
+}
+
+async fn handle_start(s: &mut Session, req: Start) -> Result<Vec<u8>> {
+    let user_id = s.check_role(&req.auth_token, Role::Moderator)?;
+    s.start_game()?;
+    s.notify_all(user_id, EventType::GameStarted);
+    position(s, s.game.id())
 }
 
-async fn handle_resign(s: &mut Session, req: Resign) -> Result {
-    let user_id = s.user_id(&req.auth_token).ok_or(())?;
-    s.game.map(|g| g.resign(&user_id));
+async fn handle_resign(s: &mut Session, req: Resign) -> Result<Vec<u8>> {
+    let user_id = s.check_user(&req.auth_token)?;
+    let id = s.game.id();
+    let game = s.game.get_mut().ok_or(Error::GameNotRunning)?;
+    game.resign(&user_id)?;
     s.check_end_conditions();
-    Ok(())
+    position(s, id)
 }
 
-async fn handle_board(s: &mut Session, req: Board) -> Result {
+async fn handle_draw(s: &mut Session, req: Draw) -> Result<Vec<u8>> {
+    let (auth_token, accept) = match &req {
+        Draw::Offer { auth_token } => (auth_token, false),
+        Draw::Accept { auth_token } => (auth_token, true),
+    };
+    let user_id = s.check_user(auth_token)?;
+    let id = s.game.id();
+    let game = s.game.get_mut().ok_or(Error::GameNotRunning)?;
+    let accepted = if accept {
+        game.accept_draw(&user_id).map(|_| true)?
+    } else {
+        game.offer_draw(&user_id)?
+    };
+    if accepted {
+        s.check_end_conditions();
+    } else {
+        s.notify_all(user_id, EventType::DrawOffered { user_id });
+    }
+    position(s, id)
+}
+
+async fn handle_board(s: &mut Session, req: Board) -> Result<Vec<u8>> {
     match req {
         Board::Deploy {
             auth_token,
             piece,
             pos,
-        } => handle_deploy(s, auth_token, piece, pos).await,
-        Board::Move { auth_token, change } => handle_move(s, auth_token, change).await,
+            ply,
+        } => handle_deploy(s, auth_token, piece, pos, ply).await,
+        Board::Move {
+            auth_token,
+            change,
+            ply,
+        } => handle_move(s, auth_token, change, ply).await,
         Board::Promote {
             auth_token,
             change,
             upgrade_to,
-        } => handle_promote(s, auth_token, change, upgrade_to).await,
+            ply,
+        } => handle_promote(s, auth_token, change, upgrade_to, ply).await,
     }
 }
 
 async fn handle_participants(s: &mut Session, req: Participants) -> Result {
-    if !s.is_owner(&req.auth_token) {
-        return Err(());
+    let user_id = s.check_role(&req.auth_token, Role::Moderator)?;
+    s.set_participants(req.participants)?;
+    s.notify_all(user_id, EventType::ParticipantsChanged);
+    s.reschedule_next_game();
+    Ok(())
+}
+
+async fn handle_match(s: &mut Session, req: Match) -> Result {
+    let user_id = s.check_role(&req.auth_token, Role::Owner)?;
+    s.start_series(req.teams, req.best_of)?;
+    s.notify_all(user_id, EventType::ParticipantsChanged);
+    s.reschedule_next_game();
+    Ok(())
+}
+
+async fn handle_queue(s: &mut Session, req: Queue) -> Result {
+    let user_id = match req {
+        Queue::Move {
+            auth_token,
+            from,
+            to,
+        } => {
+            let user_id = s.check_role(&auth_token, Role::Owner)?;
+            s.move_pairing(from, to)?;
+            user_id
+        }
+        Queue::Skip { auth_token, pos } => {
+            let user_id = s.check_role(&auth_token, Role::Owner)?;
+            s.skip_pairing(pos)?;
+            user_id
+        }
+        Queue::Insert {
+            auth_token,
+            pos,
+            pairing,
+        } => {
+            let user_id = s.check_role(&auth_token, Role::Owner)?;
+            s.insert_pairing(pos, pairing)?;
+            user_id
+        }
+    };
+    s.notify_all(user_id, EventType::QueueChanged);
+    s.reschedule_next_game();
+    Ok(())
+}
+
+async fn handle_abort(s: &mut Session, req: Abort) -> Result {
+    s.check_role(&req.auth_token, Role::Moderator)?;
+    if !s.game.did_start() {
+        return Err(Error::GameNotRunning);
     }
-    s.set_participants(req.participants).or(Err(()))?;
-    s.notify_all(UserId::OWNER, EventType::ParticipantsChanged);
+    s.end_game(GameResult::new(EndReason::Abort));
     Ok(())
 }
 
-async fn handle_join2(s: &mut Session, user_name: String, tx: oneshot::Sender<Vec<u8>>) -> Result {
-    let (user_id, auth_token) = s.add_user(user_name).or(Err(()))?;
+async fn handle_kick(s: &mut Session, req: Kick) -> Result {
+    let user_id = s.check_role(&req.auth_token, Role::Moderator)?;
+    // Moderators may only kick users below them.
+    if s.role(&req.user_id) >= s.role(&user_id) {
+        return Err(Error::InvalidRole);
+    }
+    if req.ban {
+        s.ban(req.user_id);
+    }
+    s.remove_user(req.user_id)?;
+    s.notify_all(
+        user_id,
+        EventType::UserKicked {
+            user_id: req.user_id,
+            banned: req.ban,
+        },
+    );
+    s.reschedule_next_game();
+    Ok(())
+}
+
+async fn handle_leave(s: &mut Session, req: Leave) -> Result {
+    if s.remove_spectator(&req.auth_token) {
+        s.dirty = true;
+        return Ok(());
+    }
+    let user_id = s.check_user(&req.auth_token)?;
+    s.remove_user(user_id)?;
+    s.notify_all(user_id, EventType::UserLeft { user_id });
+    s.reschedule_next_game();
+    Ok(())
+}
+
+async fn handle_transfer(s: &mut Session, req: Transfer) -> Result {
+    let user_id = s.check_role(&req.auth_token, Role::Owner)?;
+    s.transfer_ownership(user_id, req.user_id)
+}
+
+async fn handle_appoint(s: &mut Session, req: Appoint) -> Result {
+    let user_id = s.check_role(&req.auth_token, Role::Owner)?;
+    if !s.users.contains_key(&req.user_id) {
+        return Err(Error::UnknownUser);
+    } else if req.role == Role::Owner || req.user_id == user_id {
+        return Err(Error::InvalidRole);
+    }
+    s.set_role(user_id, req.user_id, req.role);
+    s.reschedule_next_game();
+    Ok(())
+}
+
+async fn handle_settings(s: &mut Session, req: Settings) -> Result {
+    let user_id = s.check_role(&req.auth_token, Role::Owner)?;
+    if let Some(time_control) = req.time_control {
+        if !time_control.is_valid() {
+            return Err(Error::InvalidTimeControl);
+        }
+    }
+    if let Some(auto_start) = req.auto_start {
+        if !utils::is_valid_auto_start(auto_start) {
+            return Err(Error::InvalidAutoStart);
+        }
+    }
+    if let Some(time_control) = req.time_control {
+        s.time_control = time_control;
+    }
+    if let Some(public) = req.public {
+        s.public = public;
+    }
+    if let Some(anonymous_spectators) = req.anonymous_spectators {
+        s.anonymous_spectators = anonymous_spectators;
+    }
+    if let Some(auto_start) = req.auto_start {
+        s.auto_start = auto_start;
+    }
+    s.notify_all(user_id, EventType::SettingsChanged);
+    if req.auto_start.is_some() && s.game.did_end() {
+        s.schedule_next_game();
+    }
+    Ok(())
+}
+
+async fn handle_join2(
+    s: &mut Session,
+    user_name: String,
+    player_token: Option<PlayerToken>,
+) -> Result<Vec<u8>> {
+    let (user_id, auth_token, player_token) = s.add_user(user_name, player_token)?;
     let json = serde_json::to_vec(&Joined {
         auth_token: &auth_token,
+        player_token: Some(&player_token),
     })
     .unwrap();
-    let _ = tx.send(json);
     s.notify_all(user_id, EventType::Joined);
-    Ok(())
+    Ok(json)
 }
 
-async fn handle_connect(
-    s: &mut Session,
-    auth_token: AuthToken,
-    tx: oneshot::Sender<Vec<u8>>,
-) -> Result {
-    let user_id = s.user_ids.get(&auth_token).ok_or(())?;
+async fn handle_connect(s: &mut Session, auth_token: AuthToken) -> Result<Vec<u8>> {
+    let user_id = s.check_user(&auth_token)?;
     let json = serde_json::to_vec(&Connected {
-        user_id,
+        user_id: &user_id,
         session: s,
     })
     .unwrap();
-    let _ = tx.send(json);
-    Ok(())
+    if s.game.map(|g| g.reconnect(&user_id)) == Some(true) {
+        s.notify_all(user_id, EventType::GameResumed);
+    }
+    Ok(json)
 }
 
 async fn handle_deploy(
@@ -157,22 +471,36 @@ async fn handle_deploy(
     auth_token: AuthToken,
     piece: String,
     pos: String,
-) -> Result {
-    let user_id = s.user_id(&auth_token).ok_or(())?;
-    let game = s.game.get_mut().ok_or(())?;
-    game.deploy_piece(&user_id, &piece, &pos).or(Err(()))?;
+    ply: Option<usize>,
+) -> Result<Vec<u8>> {
+    let user_id = s.check_user(&auth_token)?;
+    let id = s.game.id();
+    let game = s.game.get_mut().ok_or(Error::GameNotRunning)?;
+    game.check_ply(&user_id, ply)?;
+    let res = game.deploy_piece(&user_id, &piece, &pos);
+    // A player whose time ran out loses, even if the timer hasn't fired yet.
     s.check_end_conditions();
+    res?;
     s.notify_all(user_id, EventType::PieceDeployed);
-    Ok(())
+    position(s, id)
 }
 
-async fn handle_move(s: &mut Session, auth_token: AuthToken, change: String) -> Result {
-    let user_id = s.user_id(&auth_token).ok_or(())?;
-    let game = s.game.get_mut().ok_or(())?;
-    game.move_piece(&user_id, &change).or(Err(()))?;
+async fn handle_move(
+    s: &mut Session,
+    auth_token: AuthToken,
+    change: String,
+    ply: Option<usize>,
+) -> Result<Vec<u8>> {
+    let user_id = s.check_user(&auth_token)?;
+    let id = s.game.id();
+    let game = s.game.get_mut().ok_or(Error::GameNotRunning)?;
+    game.check_ply(&user_id, ply)?;
+    let res = game.move_piece(&user_id, &change);
+    // A player whose time ran out loses, even if the timer hasn't fired yet.
     s.check_end_conditions();
+    res?;
     s.notify_all(user_id, EventType::PieceMoved);
-    Ok(())
+    position(s, id)
 }
 
 async fn handle_promote(
@@ -180,20 +508,76 @@ async fn handle_promote(
     auth_token: AuthToken,
     change: String,
     upgrade_to: String,
-) -> Result {
-    let user_id = s.user_id(&auth_token).ok_or(())?;
-    let game = s.game.get_mut().ok_or(())?;
-    game.promote_piece(&user_id, &change, &upgrade_to)
-        .or(Err(()))?;
+    ply: Option<usize>,
+) -> Result<Vec<u8>> {
+    let user_id = s.check_user(&auth_token)?;
+    let id = s.game.id();
+    let game = s.game.get_mut().ok_or(Error::GameNotRunning)?;
+    game.check_ply(&user_id, ply)?;
+    let res = game.promote_piece(&user_id, &change, &upgrade_to);
+    // A player whose time ran out loses, even if the timer hasn't fired yet.
     s.check_end_conditions();
+    res?;
     s.notify_all(user_id, EventType::PiecePromoted);
+    position(s, id)
+}
+
+async fn handle_moves(s: &mut Session, id: usize, tx: oneshot::Sender<Option<Vec<u8>>>) -> Result {
+    let json = s
+        .find_game(id)
+        .map(|g| serde_json::to_vec(&Moves { moves: &g.moves }).unwrap());
+    let _ = tx.send(json);
     Ok(())
 }
 
+async fn handle_bpgn(s: &mut Session, id: usize, tx: oneshot::Sender<Option<String>>) -> Result {
+    let _ = tx.send(bpgn::export(s, id));
+    Ok(())
+}
+
+async fn handle_listing(s: &mut Session, tx: oneshot::Sender<Option<Listing>>) -> Result {
+    let _ = tx.send(s.listing());
+    Ok(())
+}
+
+async fn handle_state(s: &mut Session, auth_token: Option<AuthToken>) -> Result<Vec<u8>> {
+    s.check_watcher(auth_token.as_ref())?;
+    Ok(serde_json::to_vec(&State { session: s }).unwrap())
+}
+
 async fn handle_subscribe(
     s: &mut Session,
-    tx: oneshot::Sender<broadcast::Receiver<Vec<u8>>>,
+    auth_token: Option<AuthToken>,
+    last_event_id: Option<usize>,
+) -> Result<Subscription> {
+    s.check_watcher(auth_token.as_ref())?;
+    match auth_token.as_ref().and_then(|t| s.user_id(t)) {
+        Some(user_id) => s.connect(user_id),
+        None => s.watch(auth_token.clone())?,
+    }
+    let (stream_id, revoked) = s.open_stream(auth_token);
+    let replay = match last_event_id {
+        Some(id) => s.replay(id),
+        None => vec![s.snapshot()],
+    };
+    Ok(Subscription {
+        stream_id,
+        replay,
+        rx: s.broadcast_tx.subscribe(),
+        revoked,
+    })
+}
+
+async fn handle_disconnect(
+    s: &mut Session,
+    auth_token: Option<AuthToken>,
+    stream_id: usize,
 ) -> Result {
-    let _ = tx.send(s.broadcast_tx.subscribe());
+    s.revokers.remove(&stream_id);
+    if s.unwatch(&auth_token) {
+        return Ok(());
+    }
+    let user_id = s.check_user(&auth_token.ok_or(Error::Unauthorized)?)?;
+    s.disconnect(user_id);
     Ok(())
 }
@@ -14,40 +14,48 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::{
-    common::event::{Event, EventType},
+    common::event::{Event, EventType, Payload},
     common::*,
     config::Config,
-    data::{User, UserId},
+    data::{Delay, Role, TimeControl, User, UserId},
     sessions::Sessions,
 };
 use bughouse_rs::logic::{ChessLogic, Winner};
-pub use handler::Msg;
-use serde::Serialize;
+pub use error::Error;
+use futures::future;
+pub use handler::{Msg, Presence, Subscription};
+use serde::{Deserialize, Serialize};
+pub use series::Series;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::{
     select,
-    sync::{broadcast, mpsc},
-    time::interval,
+    sync::{broadcast, mpsc, oneshot},
+    time::{self, interval},
 };
 
+mod bpgn;
+mod error;
 mod handler;
+mod persist;
+mod san;
+mod series;
 mod utils;
 
 const BROADCAST_CHANNEL_CAPACITY: usize = 5;
 const BROADCAST_MAX_FAILURE: usize = 20;
-const GAME_DURATION: Duration = Duration::from_secs(300);
-const PROMOTE_ADDED_TIME: Duration = Duration::from_secs(3);
+const EVENT_HISTORY_CAPACITY: usize = 64;
+/// How many finished games are kept for their move logs and exports.
+const ARCHIVE_CAPACITY: usize = 16;
+/// How long to wait after a change before saving, so that a burst of changes
+/// is written at once.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
 const ZERO_SECS: Duration = Duration::from_secs(0);
 
-type Result<T> = std::result::Result<T, Error>;
-
-enum Error {
-    Error,
-}
+pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -61,15 +69,86 @@ pub struct Session {
     #[serde(skip_serializing)]
     user_ids: HashMap<AuthToken, UserId>,
     users: HashMap<UserId, User>,
+    /// Names of the users who were banned, in lower case.
+    #[serde(skip_serializing)]
+    banned: HashSet<String>,
+    /// Player tokens of the users who were banned.
+    #[serde(skip_serializing)]
+    banned_tokens: HashSet<PlayerToken>,
+    /// The token each user's rating is kept under.
+    #[serde(skip_serializing)]
+    player_tokens: HashMap<UserId, PlayerToken>,
+    /// Number of open event streams of each user.
+    #[serde(skip_serializing)]
+    connections: HashMap<UserId, usize>,
+    /// Since when the owner has had no open event stream, or `None` if they
+    /// are connected.
+    #[serde(skip_serializing)]
+    owner_away_since: Option<Instant>,
+    /// Names of the users who watch the session without playing. Anonymous
+    /// spectators have no name.
+    spectators: Vec<Option<String>>,
+    #[serde(skip_serializing)]
+    spectator_ids: HashMap<AuthToken, usize>,
+    /// Number of open event streams of each spectator, or of anonymous
+    /// spectators under `None`.
+    #[serde(skip_serializing)]
+    watchers: HashMap<Option<AuthToken>, usize>,
+    /// Senders which close the open event streams when dropped, along with
+    /// the auth tokens the streams were opened with. Keyed by stream ID.
+    #[serde(skip_serializing)]
+    revokers: HashMap<usize, (Option<AuthToken>, oneshot::Sender<()>)>,
+    #[serde(skip_serializing)]
+    next_stream_id: usize,
     participants: Vec<UserId>,
+    time_control: TimeControl,
+    public: bool,
+    anonymous_spectators: bool,
+    /// Countdown before the next game starts on its own once a game ended. If
+    /// zero, games are only started by hand.
+    auto_start: Duration,
+    /// When the next game starts on its own, if it does.
     #[serde(skip_serializing)]
+    next_game_at: Option<Instant>,
+    /// The match between two fixed teams, if one is being played.
+    series: Option<Series>,
+    /// The upcoming games, the next one first.
     queue: VecDeque<((UserId, UserId), (UserId, UserId))>,
     game: GameState,
+    /// The last games which have ended along with their IDs and results, the
+    /// oldest first.
     #[serde(skip_serializing)]
-    broadcast_tx: broadcast::Sender<Vec<u8>>,
+    archive: VecDeque<(usize, Game, GameResult)>,
+    #[serde(skip_serializing)]
+    broadcast_tx: broadcast::Sender<Payload>,
     #[serde(skip_serializing)]
     failed_broadcasts: usize,
     #[serde(skip_serializing)]
+    event_id: usize,
+    #[serde(skip_serializing)]
+    history: VecDeque<Payload>,
+    /// `true` if the state changed since it was last saved.
+    #[serde(skip_serializing)]
+    dirty: bool,
+    /// When the pending changes will be saved.
+    #[serde(skip_serializing)]
+    save_at: Option<Instant>,
+    /// Number of times the session was saved. Saves are written in the
+    /// background, so this keeps an older one from overwriting a newer one.
+    #[serde(skip_serializing)]
+    saves: usize,
+    /// The number of the last save which was written.
+    #[serde(skip_serializing)]
+    written: Arc<tokio::sync::Mutex<usize>>,
+    /// When a user last did something which changed the session.
+    #[serde(skip_serializing)]
+    last_action: Instant,
+    /// `true` if the users were warned about the session expiring.
+    #[serde(skip_serializing)]
+    expiry_warned: bool,
+    #[serde(skip_serializing)]
+    closed: bool,
+    #[serde(skip_serializing)]
     config: Arc<Config>,
 }
 
@@ -83,6 +162,14 @@ impl Session {
         if !utils::is_valid_user_name(owner_name) {
             return None;
         }
+        Some(Self::empty(sessions, config, session_id))
+    }
+
+    fn empty(
+        sessions: Sessions,
+        config: Arc<Config>,
+        session_id: SessionId,
+    ) -> (Session, mpsc::Sender<Msg>) {
         let (tx, rx) = mpsc::channel(config.session_capacity());
         let (broadcast_tx, _) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
         let session = Self {
@@ -91,21 +178,52 @@ impl Session {
             rx,
             user_ids: HashMap::with_capacity(0),
             users: HashMap::with_capacity(0),
+            banned: HashSet::new(),
+            banned_tokens: HashSet::new(),
+            player_tokens: HashMap::new(),
+            connections: HashMap::new(),
+            owner_away_since: None,
+            spectators: Vec::with_capacity(0),
+            spectator_ids: HashMap::with_capacity(0),
+            watchers: HashMap::new(),
+            revokers: HashMap::new(),
+            next_stream_id: 0,
             participants: Vec::with_capacity(0),
+            time_control: TimeControl::default(),
+            public: false,
+            anonymous_spectators: true,
+            auto_start: ZERO_SECS,
+            next_game_at: None,
+            series: None,
             queue: VecDeque::with_capacity(0),
             game: GameState::Starting,
+            archive: VecDeque::with_capacity(0),
             broadcast_tx,
             failed_broadcasts: 0,
+            event_id: 0,
+            history: VecDeque::with_capacity(EVENT_HISTORY_CAPACITY),
+            dirty: false,
+            save_at: None,
+            saves: 0,
+            written: Arc::new(tokio::sync::Mutex::new(0)),
+            last_action: Instant::now(),
+            expiry_warned: false,
+            closed: false,
             config,
         };
-        Some((session, tx))
+        (session, tx)
+    }
+
+    pub fn id(&self) -> &SessionId {
+        &self.id
     }
 
     pub fn spawn(mut self) {
         tokio::spawn(async move {
-            let mut timer = interval(self.config.tick());
             let mut broadcast_timer = interval(self.config.broadcast_interval());
             loop {
+                let deadline = self.game.get().and_then(|g| g.deadline());
+                let idle_deadline = time::Instant::from_std(self.idle_deadline());
                 select! {
                     msg = self.rx.recv() => {
                         match msg {
@@ -113,20 +231,78 @@ impl Session {
                             _ => break
                         }
                     },
-                    _ = timer.tick() => handler::handle_timer(&mut self),
+                    _ = flag_fall(deadline) => handler::handle_timer(&mut self),
+                    _ = flag_fall(self.next_game_at) => handler::handle_next_game_timer(&mut self),
+                    _ = time::delay_until(idle_deadline) => handler::handle_idle_timer(&mut self),
                     _ = broadcast_timer.tick() => handler::handle_broadcast_timer(&mut self),
+                    _ = flag_fall(self.save_at) => {
+                        self.save();
+                        self.dirty = false;
+                        self.save_at = None;
+                    },
+                }
+                if self.dirty && self.save_at.is_none() {
+                    self.save_at = Some(Instant::now() + SAVE_DELAY);
                 }
             }
+            self.discard().await;
             self.sessions.remove(&self.id).await;
         });
     }
 
-    fn is_owner(&self, auth_token: &AuthToken) -> bool {
-        let user_id = self.user_ids.get(auth_token);
-        if let Some(user_id) = user_id {
-            *user_id == UserId::OWNER
+    /// Closes the session after telling the subscribers why.
+    fn close(&mut self, reason: CloseReason) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        self.notify_all(UserId::OWNER, EventType::SessionClosed { reason });
+        self.rx.close();
+    }
+
+    /// Postpones the expiry of the session.
+    fn touch(&mut self) {
+        self.last_action = Instant::now();
+        self.expiry_warned = false;
+    }
+
+    fn expires_at(&self) -> Instant {
+        let timeout = if self.game.did_start() {
+            self.config.game_timeout()
         } else {
-            false
+            self.config.lobby_timeout()
+        };
+        self.last_action + timeout
+    }
+
+    /// Returns when the users should be warned about the session expiring, or
+    /// when the session expires if they were already warned.
+    fn idle_deadline(&self) -> Instant {
+        let expires_at = self.expires_at();
+        if self.expiry_warned {
+            return expires_at;
+        }
+        expires_at
+            .checked_sub(self.config.expiry_warning())
+            .unwrap_or(expires_at)
+            .max(self.last_action)
+    }
+
+    /// Warns the users if the session is about to expire, or closes it if it
+    /// already expired.
+    fn check_expiry(&mut self) {
+        let now = Instant::now();
+        let expires_at = self.expires_at();
+        if now >= expires_at {
+            self.close(CloseReason::Idle);
+        } else if !self.expiry_warned {
+            self.expiry_warned = true;
+            self.notify_all(
+                UserId::OWNER,
+                EventType::ExpiryWarning {
+                    expires_in: expires_at - now,
+                },
+            );
         }
     }
 
@@ -134,31 +310,384 @@ impl Session {
         self.user_ids.get(auth_token).cloned()
     }
 
-    fn add_user(&mut self, name: String) -> Result<(UserId, AuthToken)> {
-        if !utils::is_valid_user_name(&name) || self.user_ids.len() >= self.config.max_user() {
-            return Err(Error::Error);
+    /// Returns the user with the given auth token.
+    fn check_user(&self, auth_token: &AuthToken) -> Result<UserId> {
+        self.user_id(auth_token).ok_or(Error::Unauthorized)
+    }
+
+    fn role(&self, user_id: &UserId) -> Role {
+        self.users
+            .get(user_id)
+            .map_or(Role::Spectator, |u| u.role())
+    }
+
+    fn owner(&self) -> Option<UserId> {
+        self.users
+            .iter()
+            .find(|(_, u)| u.role() == Role::Owner)
+            .map(|(user_id, _)| *user_id)
+    }
+
+    /// Returns the user with the given auth token if their role is at least
+    /// `role`.
+    fn check_role(&self, auth_token: &AuthToken, role: Role) -> Result<UserId> {
+        let user_id = self.check_user(auth_token)?;
+        if self.role(&user_id) >= role {
+            Ok(user_id)
+        } else if role == Role::Owner {
+            Err(Error::NotOwner)
+        } else {
+            Err(Error::NotModerator)
+        }
+    }
+
+    /// Changes the role of the user. Spectators are taken out of the upcoming
+    /// games.
+    fn set_role(&mut self, caused_by: UserId, user_id: UserId, role: Role) {
+        if let Some(u) = self.users.get_mut(&user_id) {
+            *u.role_mut() = role;
         }
-        let user_id = UserId::new(self.user_ids.len() as u8);
+        if role == Role::Spectator {
+            self.withdraw(user_id);
+        }
+        if role == Role::Owner {
+            self.reset_owner_presence();
+        }
+        self.notify_all(caused_by, EventType::RoleChanged { user_id, role });
+    }
+
+    /// Makes `to` the owner of the session, and the previous owner a
+    /// moderator.
+    fn transfer_ownership(&mut self, caused_by: UserId, to: UserId) -> Result<()> {
+        match self.users.get(&to).map(|u| u.role()) {
+            None => return Err(Error::UnknownUser),
+            Some(Role::Owner) | Some(Role::Spectator) => return Err(Error::InvalidRole),
+            _ => (),
+        }
+        if let Some(owner) = self.owner() {
+            self.set_role(caused_by, owner, Role::Moderator);
+        }
+        self.set_role(caused_by, to, Role::Owner);
+        Ok(())
+    }
+
+    /// Counts an event stream opened by the user.
+    fn connect(&mut self, user_id: UserId) {
+        *self.connections.entry(user_id).or_insert(0) += 1;
+        if self.role(&user_id) == Role::Owner {
+            self.owner_away_since = None;
+        }
+    }
+
+    /// Counts an event stream closed by the user.
+    fn disconnect(&mut self, user_id: UserId) {
+        if let Some(n) = self.connections.get_mut(&user_id) {
+            *n -= 1;
+            if *n == 0 {
+                self.connections.remove(&user_id);
+                if self.role(&user_id) == Role::Owner {
+                    self.owner_away_since = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    /// Starts the hand-off timer afresh if the owner has no open event stream,
+    /// or stops it if they have one.
+    fn reset_owner_presence(&mut self) {
+        self.owner_away_since = match self.owner() {
+            Some(owner) if !self.connections.contains_key(&owner) => Some(Instant::now()),
+            _ => None,
+        };
+    }
+
+    /// Hands the session over to the connected user who joined first if the
+    /// owner was away for too long.
+    fn check_owner_presence(&mut self) {
+        let away = self
+            .owner_away_since
+            .is_some_and(|since| since.elapsed() >= self.config.owner_timeout());
+        if !away {
+            return;
+        }
+        let successor = self
+            .connections
+            .keys()
+            .filter(|user_id| self.role(user_id) >= Role::Player)
+            .min()
+            .cloned();
+        if let Some(successor) = successor {
+            let _ = self.transfer_ownership(successor, successor);
+        }
+    }
+
+    /// Adds a user who plays under the given player token, or under a new one
+    /// if none is given.
+    fn add_user(
+        &mut self,
+        name: String,
+        player_token: Option<PlayerToken>,
+    ) -> Result<(UserId, AuthToken, PlayerToken)> {
+        let name = utils::normalize_name(&name);
+        if !utils::is_valid_user_name(&name) {
+            return Err(Error::InvalidName);
+        } else if player_token.as_ref().is_some_and(|t| !t.is_valid()) {
+            return Err(Error::InvalidPlayerToken);
+        } else if self.is_banned(&name, player_token.as_ref()) {
+            return Err(Error::Banned);
+        } else if self.user_ids.len() >= self.config.max_user() {
+            return Err(Error::SessionFull);
+        }
+        // The owner who creates the session gets `UserId::OWNER`. Everyone else
+        // gets the lowest free ID, so that the IDs of users who left are
+        // reused.
+        let user_id = if self.users.is_empty() {
+            UserId::OWNER
+        } else {
+            (1..=u8::MAX)
+                .map(UserId::new)
+                .find(|id| !self.users.contains_key(id))
+                .ok_or(Error::SessionFull)?
+        };
         let auth_token = AuthToken::new();
-        let user = User::new(name).ok_or(Error::Error)?;
+        let user = User::new(name).ok_or(Error::InvalidName)?;
+        let player_token = player_token.unwrap_or_else(PlayerToken::new);
         self.user_ids.insert(auth_token.clone(), user_id);
         self.users.insert(user_id, user);
-        Ok((user_id, auth_token))
+        self.player_tokens.insert(user_id, player_token.clone());
+        Ok((user_id, auth_token, player_token))
+    }
+
+    /// Keeps the user from joining again, under any spelling of their name or
+    /// with their player token.
+    fn ban(&mut self, user_id: UserId) {
+        if let Some(u) = self.users.get(&user_id) {
+            self.banned.insert(u.name().to_lowercase());
+        }
+        if let Some(player_token) = self.player_tokens.get(&user_id) {
+            self.banned_tokens.insert(player_token.clone());
+        }
+    }
+
+    /// Returns `true` if a user with the name or the player token was banned.
+    /// The name should be normalized.
+    fn is_banned(&self, name: &str, player_token: Option<&PlayerToken>) -> bool {
+        self.banned.contains(&name.to_lowercase())
+            || player_token.is_some_and(|t| self.banned_tokens.contains(t))
+    }
+
+    /// Removes the user from the session, revoking their auth token. If they
+    /// are playing, they resign first.
+    fn remove_user(&mut self, user_id: UserId) -> Result<()> {
+        if self.role(&user_id) == Role::Owner {
+            return Err(Error::OwnerCannotLeave);
+        } else if !self.users.contains_key(&user_id) {
+            return Err(Error::UnknownUser);
+        }
+        // Take them out of the upcoming games first, so that the next game
+        // scheduled at the end of this one doesn't include them.
+        self.withdraw(user_id);
+        if let Some(game) = self.game.get_mut() {
+            if game.resign(&user_id).is_ok() {
+                self.check_end_conditions();
+            }
+        }
+        let auth_tokens: Vec<_> = self
+            .user_ids
+            .iter()
+            .filter(|(_, id)| **id == user_id)
+            .map(|(auth_token, _)| auth_token.clone())
+            .collect();
+        for auth_token in &auth_tokens {
+            self.user_ids.remove(auth_token);
+            self.revoke(auth_token);
+        }
+        self.users.remove(&user_id);
+        self.player_tokens.remove(&user_id);
+        self.connections.remove(&user_id);
+        Ok(())
+    }
+
+    /// Returns the ID of a new event stream opened with the auth token, along
+    /// with a receiver which resolves once the auth token is revoked.
+    fn open_stream(&mut self, auth_token: Option<AuthToken>) -> (usize, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 1;
+        self.revokers.insert(stream_id, (auth_token, tx));
+        (stream_id, rx)
+    }
+
+    /// Closes the event streams opened with the auth token.
+    fn revoke(&mut self, auth_token: &AuthToken) {
+        self.revokers
+            .retain(|_, (t, _)| t.as_ref() != Some(auth_token));
+    }
+
+    /// Takes the user out of the upcoming games. A match they play in is
+    /// called off.
+    fn withdraw(&mut self, user_id: UserId) {
+        self.participants.retain(|id| *id != user_id);
+        self.queue
+            .retain(|&((a, b), (c, d))| ![a, b, c, d].contains(&user_id));
+        let in_series = self.series.as_ref().is_some_and(|series| {
+            let ((a, b), (c, d)) = series.teams;
+            [a, b, c, d].contains(&user_id)
+        });
+        if in_series {
+            self.series = None;
+        }
+    }
+
+    fn add_spectator(&mut self, name: Option<String>) -> Result<AuthToken> {
+        match &name {
+            Some(name) if !utils::is_valid_user_name(name) => return Err(Error::InvalidName),
+            None if !self.anonymous_spectators => return Err(Error::AnonymousSpectator),
+            _ => (),
+        }
+        if self.spectators.len() >= self.config.max_spectator() {
+            // Make room by dropping a spectator who isn't watching.
+            let idle = self
+                .spectator_ids
+                .keys()
+                .find(|t| !self.watchers.contains_key(&Some((*t).clone())))
+                .cloned()
+                .ok_or(Error::SpectatorsFull)?;
+            self.remove_spectator(&idle);
+        }
+        let auth_token = AuthToken::new();
+        self.spectator_ids
+            .insert(auth_token.clone(), self.spectators.len());
+        self.spectators.push(name);
+        Ok(auth_token)
+    }
+
+    /// Removes the spectator with the given auth token. Returns `false` if
+    /// there is no such spectator.
+    fn remove_spectator(&mut self, auth_token: &AuthToken) -> bool {
+        let i = match self.spectator_ids.remove(auth_token) {
+            Some(i) => i,
+            None => return false,
+        };
+        self.spectators.swap_remove(i);
+        self.revoke(auth_token);
+        // The last spectator took the place of the removed one.
+        let last = self.spectators.len();
+        if let Some(j) = self.spectator_ids.values_mut().find(|j| **j == last) {
+            *j = i;
+        }
+        true
+    }
+
+    /// Counts an event stream opened by a spectator. Every open stream takes
+    /// up a spectator slot until it is closed.
+    fn watch(&mut self, auth_token: Option<AuthToken>) -> Result<()> {
+        if self.watchers.values().sum::<usize>() >= self.config.max_spectator() {
+            return Err(Error::SpectatorsFull);
+        }
+        *self.watchers.entry(auth_token).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Counts an event stream closed by a spectator. Returns `false` if the
+    /// holder of the auth token has no open stream as a spectator.
+    fn unwatch(&mut self, auth_token: &Option<AuthToken>) -> bool {
+        match self.watchers.get_mut(auth_token) {
+            Some(n) => {
+                *n -= 1;
+                if *n == 0 {
+                    self.watchers.remove(auth_token);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Succeeds if the holder of the auth token may watch the session. Without
+    /// an auth token, this depends on whether anonymous spectators are allowed.
+    fn check_watcher(&self, auth_token: Option<&AuthToken>) -> Result<()> {
+        match auth_token {
+            Some(t) if self.user_ids.contains_key(t) || self.spectator_ids.contains_key(t) => {
+                Ok(())
+            }
+            Some(_) => Err(Error::Unauthorized),
+            None if self.anonymous_spectators => Ok(()),
+            None => Err(Error::AnonymousSpectator),
+        }
+    }
+
+    /// Returns a summary of the session if it is public.
+    fn listing(&self) -> Option<resp::Listing> {
+        if !self.public {
+            return None;
+        }
+        let owner_name = self.users.get(&self.owner()?)?.name().to_owned();
+        Some(resp::Listing {
+            session_id: self.id.clone(),
+            owner_name,
+            users: self.users.len(),
+            max_users: self.config.max_user(),
+            participants: self.participants.len(),
+            game_state: self.game.name(),
+            game_running: self.game.did_start(),
+        })
     }
 
+    /// Changes the participants of the upcoming games. The rest of the
+    /// current schedule is dropped, so a new one is made for the new
+    /// participants.
     fn set_participants(&mut self, participants: Vec<UserId>) -> Result<()> {
-        if !self.game.is_starting() || participants.iter().any(|p| self.users.get(p).is_none()) {
-            return Err(Error::Error);
+        if self.game.did_start() {
+            return Err(Error::GameRunning);
+        } else if participants.iter().any(|p| self.users.get(p).is_none()) {
+            return Err(Error::UnknownUser);
+        } else if participants.iter().any(|p| self.role(p) < Role::Player) {
+            return Err(Error::NotPlayer);
         }
         self.participants = participants;
+        self.series = None;
+        self.queue.clear();
+        // Make the new schedule right away, so everyone sees who plays next.
+        let _ = self.next_pairing();
         Ok(())
     }
 
-    fn fill_queue(&mut self) -> Result<()> {
+    /// Starts a best-of-`best_of` match between two fixed teams, replacing the
+    /// participants and the queue.
+    fn start_series(
+        &mut self,
+        teams: ((UserId, UserId), (UserId, UserId)),
+        best_of: usize,
+    ) -> Result<()> {
+        let ((a, b), (c, d)) = teams;
+        let players = vec![a, b, c, d];
+        if self.game.did_start() {
+            return Err(Error::GameRunning);
+        } else if !Series::is_valid_length(best_of) {
+            return Err(Error::InvalidSeriesLength);
+        } else if players.iter().any(|p| !self.users.contains_key(p)) {
+            return Err(Error::UnknownUser);
+        } else if players.iter().any(|p| self.role(p) < Role::Player) {
+            return Err(Error::NotPlayer);
+        } else if players.iter().collect::<HashSet<_>>().len() != players.len() {
+            return Err(Error::InvalidPairing);
+        }
+        self.queue.clear();
+        self.series = Some(Series::new(teams, best_of));
+        self.participants = players;
+        Ok(())
+    }
+
+    /// Makes a new schedule from the current participants once the previous
+    /// one was played in full. During a match, the games left in the series
+    /// are scheduled instead.
+    fn fill_queue(&mut self) {
         if self.queue.len() > 0 {
-            return Ok(());
-        } else if !self.game.is_starting() {
-            return Err(Error::Error);
+            return;
+        } else if let Some(series) = &self.series {
+            self.queue.extend(series.next_pairing());
+            return;
         }
         let pairings = utils::create_pairings(self.participants.len() as u8);
         self.queue = pairings
@@ -176,76 +705,279 @@ impl Session {
                 )
             })
             .collect();
+    }
+
+    /// Returns the players of the next game. Games in the queue are played
+    /// whatever the participants are, since they may have been added by hand.
+    fn next_pairing(&mut self) -> Result<((UserId, UserId), (UserId, UserId))> {
+        if let Some(&pairing) = self.queue.front() {
+            return Ok(pairing);
+        }
+        let count = self.participants.len();
+        if self.series.is_none() && (count < 4 || count > self.config.max_participant()) {
+            return Err(Error::InvalidParticipantCount);
+        }
+        self.fill_queue();
+        self.queue.front().cloned().ok_or(Error::NoPairings)
+    }
+
+    fn move_pairing(&mut self, from: usize, to: usize) -> Result<()> {
+        if to >= self.queue.len() {
+            return Err(Error::InvalidQueuePosition);
+        }
+        let pairing = self.queue.remove(from).ok_or(Error::InvalidQueuePosition)?;
+        self.queue.insert(to, pairing);
+        Ok(())
+    }
+
+    fn skip_pairing(&mut self, pos: usize) -> Result<()> {
+        self.queue
+            .remove(pos)
+            .map(|_| ())
+            .ok_or(Error::InvalidQueuePosition)
+    }
+
+    /// Adds a game with the given players to the queue, at the end if no
+    /// position is given. The players don't have to be participants.
+    fn insert_pairing(
+        &mut self,
+        pos: Option<usize>,
+        pairing: ((UserId, UserId), (UserId, UserId)),
+    ) -> Result<()> {
+        let pos = pos.unwrap_or(self.queue.len());
+        let ((a, b), (c, d)) = pairing;
+        let players = [a, b, c, d];
+        if pos > self.queue.len() {
+            return Err(Error::InvalidQueuePosition);
+        } else if players.iter().any(|p| !self.users.contains_key(p)) {
+            return Err(Error::UnknownUser);
+        } else if players.iter().any(|p| self.role(p) < Role::Player) {
+            return Err(Error::NotPlayer);
+        } else if players.iter().collect::<HashSet<_>>().len() != players.len() {
+            return Err(Error::InvalidPairing);
+        }
+        self.queue.insert(pos, pairing);
         Ok(())
     }
 
     fn start_game(&mut self) -> Result<()> {
-        if self.participants.len() < 4
-            || self.participants.len() > self.config.max_participant()
-            || self.game.did_start()
-        {
-            return Err(Error::Error);
-        }
-        self.fill_queue()?;
-        let active_participants = self.queue.pop_front().ok_or(Error::Error)?;
+        if self.game.did_start() {
+            return Err(Error::GameRunning);
+        }
+        let active_participants = self.next_pairing()?;
+        self.queue.pop_front();
+        self.next_game_at = None;
         let id = self.game.id() + 1;
-        let game = Game::new(active_participants);
+        let game = Game::new(active_participants, self.time_control);
         self.game = GameState::Started { id, game };
         Ok(())
     }
 
     fn tick(&mut self) {
-        self.game.map(|g| {
-            g.update_remaining_time(true);
-            g.refresh_clock(true);
-            g.update_remaining_time(false);
-            g.refresh_clock(false);
-        });
+        self.game.map(Game::update_clocks);
         self.check_end_conditions();
     }
 
+    /// Returns the game with the given ID, whether it is still running or not.
+    fn find_game(&self, id: usize) -> Option<&Game> {
+        match &self.game {
+            GameState::Started { id: current, game } if *current == id => Some(game),
+            _ => self.archived(id).map(|(game, _)| game),
+        }
+    }
+
+    /// Returns the game with the given ID along with its result, if it ended
+    /// recently enough to be kept.
+    fn archived(&self, id: usize) -> Option<(&Game, &GameResult)> {
+        self.archive
+            .iter()
+            .find(|(archived, _, _)| *archived == id)
+            .map(|(_, game, result)| (game, result))
+    }
+
     fn check_end_conditions(&mut self) {
-        if let Some(g) = self.game.get() {
-            let ((u1, u2), (u3, u4)) = g.active_participants;
-            match g.winner() {
-                Winner::W1 | Winner::B2 => {
-                    self.users.get_mut(&u1).map(|u| *(u.score_mut()) += 1);
-                    self.users.get_mut(&u2).map(|u| *(u.score_mut()) += 1);
-                    self.game = GameState::Ended { id: self.game.id() };
-                    self.notify_all(
-                        u1,
-                        EventType::GameEnded {
-                            winners: Some((u1, u2)),
-                        },
-                    );
-                }
-                Winner::B1 | Winner::W2 => {
-                    self.users.get_mut(&u3).map(|u| *(u.score_mut()) += 1);
-                    self.users.get_mut(&u4).map(|u| *(u.score_mut()) += 1);
-                    self.game = GameState::Ended { id: self.game.id() };
-                    self.notify_all(
-                        u3,
-                        EventType::GameEnded {
-                            winners: Some((u3, u4)),
-                        },
-                    );
-                }
-                Winner::P => {
-                    self.game = GameState::Ended { id: self.game.id() };
-                    self.notify_all(UserId::OWNER, EventType::GameEnded { winners: None });
-                }
-                _ => (),
+        if let Some(result) = self.game.get().and_then(|g| g.result()) {
+            self.end_game(result);
+        }
+    }
+
+    fn end_game(&mut self, result: GameResult) {
+        if !self.game.did_start() {
+            return;
+        }
+        if let Some((u1, u2)) = result.winners {
+            self.users.get_mut(&u1).map(|u| *(u.score_mut()) += 1);
+            self.users.get_mut(&u2).map(|u| *(u.score_mut()) += 1);
+        }
+        let id = self.game.id();
+        let ended = GameState::Ended {
+            id,
+            result: result.clone(),
+        };
+        let mut decided = false;
+        if let GameState::Started { game, .. } = std::mem::replace(&mut self.game, ended) {
+            let players = game.active_participants;
+            if self.archive.len() >= ARCHIVE_CAPACITY {
+                self.archive.pop_front();
+            }
+            self.archive.push_back((id, game, result.clone()));
+            self.rate(players, &result);
+            decided = self.record_series(players, &result);
+        }
+        let caused_by = result.player.unwrap_or(UserId::OWNER);
+        let reason = result.reason;
+        self.notify_all(caused_by, EventType::GameEnded(result));
+        let outcome = self
+            .series
+            .as_ref()
+            .filter(|_| decided)
+            .map(|series| (series.winners(), series.score));
+        if let Some((winners, score)) = outcome {
+            // The next games follow the schedule again.
+            self.series = None;
+            self.notify_all(caused_by, EventType::MatchEnded { winners, score });
+        }
+        match reason {
+            EndReason::Abort | EndReason::Termination => (),
+            _ => self.schedule_next_game(),
+        }
+    }
+
+    /// Updates the ratings of the players, and of their teams if they play a
+    /// match. Games which were cut short are not rated.
+    fn rate(&self, players: ((UserId, UserId), (UserId, UserId)), result: &GameResult) {
+        let ((a, b), (c, d)) = players;
+        let score = match (result.reason, result.winners) {
+            (EndReason::Abort, _) | (EndReason::Termination, _) => return,
+            (_, Some((w, _))) if w == a || w == b => 1.0,
+            (_, Some(_)) => 0.0,
+            (_, None) => 0.5,
+        };
+        let entries: Option<Vec<_>> = [a, b, c, d]
+            .iter()
+            .map(|id| Some((self.player_tokens.get(id)?, self.users.get(id)?.name())))
+            .collect();
+        let entries = match entries {
+            Some(entries) => entries,
+            None => return,
+        };
+        // Someone who joined twice with the same player token can't be rated
+        // against themselves.
+        let unique: HashSet<_> = entries.iter().map(|(t, _)| t).collect();
+        if unique.len() != entries.len() {
+            return;
+        }
+        let fixed = self
+            .series
+            .as_ref()
+            .is_some_and(|series| series.is_between(&players));
+        let ratings = self.sessions.ratings().clone();
+        let e = &entries;
+        ratings.record(((e[0], e[1]), (e[2], e[3])), score, fixed);
+        tokio::spawn(async move { ratings.save().await });
+    }
+
+    /// Counts the result towards the match. Returns `true` if this decided
+    /// the match, in which case the games left in the queue are dropped.
+    fn record_series(
+        &mut self,
+        players: ((UserId, UserId), (UserId, UserId)),
+        result: &GameResult,
+    ) -> bool {
+        let decided = match &mut self.series {
+            Some(series) => series.record(&players, result) && series.is_over(),
+            None => false,
+        };
+        if decided {
+            self.queue.clear();
+        }
+        decided
+    }
+
+    /// Starts the countdown to the next game if games start on their own, and
+    /// tells the users who plays next. If the next game can't start, the
+    /// countdown is cancelled.
+    fn schedule_next_game(&mut self) {
+        self.next_game_at = None;
+        if self.closed || self.game.did_start() {
+            return;
+        }
+        // This also makes a new schedule if needed, so everyone sees who plays
+        // next.
+        let next = self.next_pairing();
+        if self.auto_start == ZERO_SECS {
+            return;
+        }
+        if let Ok(active_participants) = next {
+            self.next_game_at = Some(Instant::now() + self.auto_start);
+            self.notify_all(
+                UserId::OWNER,
+                EventType::NextGameIn {
+                    starts_in: self.auto_start,
+                    active_participants,
+                },
+            );
+        }
+    }
+
+    /// Restarts a running countdown, since the next game might have changed.
+    fn reschedule_next_game(&mut self) {
+        if self.next_game_at.is_some() {
+            self.schedule_next_game();
+        }
+    }
+
+    /// Returns the events which were sent after `last_event_id`. If some of
+    /// them were already dropped from the history, a snapshot of the current
+    /// state is returned instead.
+    fn replay(&self, last_event_id: usize) -> Vec<Payload> {
+        if last_event_id == self.event_id {
+            return Vec::new();
+        }
+        match self.history.front() {
+            Some(p) if p.id <= last_event_id.saturating_add(1) && last_event_id < self.event_id => {
+                self.history
+                    .iter()
+                    .filter(|p| p.id > last_event_id)
+                    .cloned()
+                    .collect()
             }
+            _ => vec![self.snapshot()],
+        }
+    }
+
+    fn snapshot(&self) -> Payload {
+        let ev = Event {
+            id: self.event_id,
+            caused_by: UserId::OWNER,
+            ev: EventType::Snapshot,
+            session: &self,
+        };
+        Payload {
+            id: self.event_id,
+            json: ev.to_json(),
         }
     }
 
     fn notify_all(&mut self, caused_by: UserId, ev: EventType) {
+        let ev_changes_state = ev.changes_state();
         let ev = Event {
+            id: self.event_id + 1,
             caused_by,
             ev,
             session: &self,
         };
-        match self.broadcast_tx.send(ev.to_message()) {
+        let payload = Payload {
+            id: self.event_id + 1,
+            json: ev.to_json(),
+        };
+        self.event_id = payload.id;
+        if self.history.len() >= EVENT_HISTORY_CAPACITY {
+            self.history.pop_front();
+        }
+        self.history.push_back(payload.clone());
+        self.dirty |= ev_changes_state;
+        match self.broadcast_tx.send(payload) {
             Ok(_) => self.failed_broadcasts = 0,
             _ => self.failed_broadcasts += 1,
         }
@@ -255,6 +987,24 @@ impl Session {
     }
 }
 
+/// Resolves once `deadline` is reached, or never if there is no deadline.
+async fn flag_fall(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::delay_until(time::Instant::from_std(deadline)).await,
+        None => future::pending().await,
+    }
+}
+
+/// `CloseReason` tells the subscribers why a session was closed.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CloseReason {
+    /// The owner deleted the session.
+    Deleted,
+    /// Nobody acted for too long.
+    Idle,
+}
+
 #[derive(Serialize)]
 #[serde(tag = "state")]
 #[serde(rename_all = "camelCase")]
@@ -268,14 +1018,16 @@ pub enum GameState {
     #[serde(rename_all = "camelCase")]
     Ended {
         id: usize,
+        result: GameResult,
     },
 }
 
 impl GameState {
-    fn is_starting(&self) -> bool {
+    fn name(&self) -> &'static str {
         match self {
-            Self::Starting => true,
-            _ => false,
+            Self::Starting => "starting",
+            Self::Started { .. } => "started",
+            Self::Ended { .. } => "ended",
         }
     }
 
@@ -288,7 +1040,7 @@ impl GameState {
 
     fn did_end(&self) -> bool {
         match self {
-            Self::Ended { id } => true,
+            Self::Ended { .. } => true,
             _ => false,
         }
     }
@@ -304,7 +1056,7 @@ impl GameState {
         match self {
             Self::Starting => 0,
             Self::Started { id, game } => *id,
-            Self::Ended { id } => *id,
+            Self::Ended { id, .. } => *id,
         }
     }
 
@@ -323,6 +1075,42 @@ impl GameState {
     }
 }
 
+/// `GameResult` describes how a game ended.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameResult {
+    /// The winning team, or `None` if nobody won.
+    pub winners: Option<(UserId, UserId)>,
+    pub reason: EndReason,
+    /// The board on which the game was decided, either 1 or 2.
+    pub board: Option<u8>,
+    /// The player who caused the game to end, e.g. by getting checkmated.
+    pub player: Option<UserId>,
+}
+
+impl GameResult {
+    fn new(reason: EndReason) -> Self {
+        Self {
+            winners: None,
+            reason,
+            board: None,
+            player: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EndReason {
+    Checkmate,
+    Flag,
+    Resignation,
+    Draw,
+    DrawAgreement,
+    Abort,
+    Termination,
+}
+
 /// `Game` holds game related data.
 pub struct Game {
     /// Active participants.
@@ -332,25 +1120,107 @@ pub struct Game {
     /// follows: ((white, black), (black, white)).
     pub active_participants: ((UserId, UserId), (UserId, UserId)),
     /// For each board, we have a clock, which is used for recalculating the
-    /// remaining time of the currently active player. If the `bool` value is
-    /// `true`, the clock is paused.
-    pub clock: ((Instant, bool), (Instant, bool)),
+    /// remaining time of the currently active player.
+    pub clock: (Clock, Clock),
     /// Remaining time for each user. Follows the same order as
     /// `active_participants`.
     pub remaining_time: ((Duration, Duration), (Duration, Duration)),
+    pub time_control: TimeControl,
+    /// The player who resigned, if any.
+    pub resigned: Option<UserId>,
+    /// The player who offered a draw, if the offer still stands.
+    pub draw_offer: Option<UserId>,
+    /// The player who accepted a draw offer, if any.
+    pub draw_accepted: Option<UserId>,
+    /// Every move made in this game, in the order they were made.
+    pub moves: Vec<Move>,
+    pub started: Instant,
+    pub date: SystemTime,
+    /// Players who have to reconnect before the clocks start running again.
+    pub awaiting: HashSet<UserId>,
     pub logic: ChessLogic,
 }
 
+/// `Clock` keeps track of the time spent by the active player of a board.
+pub struct Clock {
+    /// The last time the remaining time was recalculated.
+    pub since: Instant,
+    /// If `true`, the clock is paused.
+    pub paused: bool,
+    /// Time spent by the active player on their current move.
+    pub spent: Duration,
+}
+
+/// `Move` is an entry in the move log of a game.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Move {
+    /// The board on which the move was made, either 1 or 2.
+    pub board: u8,
+    /// `true` if the move was made by the white player of the board.
+    pub white: bool,
+    pub user_id: UserId,
+    pub notation: String,
+    #[serde(flatten)]
+    pub kind: MoveKind,
+    /// Remaining time of the white and the black player of the board after the
+    /// move.
+    pub remaining_time: (Duration, Duration),
+    /// Time elapsed between the start of the game and the move.
+    pub timestamp: Duration,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum MoveKind {
+    #[serde(rename_all = "camelCase")]
+    Deploy { piece: String, pos: String },
+    #[serde(rename_all = "camelCase")]
+    Move { change: String },
+    #[serde(rename_all = "camelCase")]
+    Promote { change: String, upgrade_to: String },
+}
+
+impl MoveKind {
+    fn notation(&self) -> String {
+        match self {
+            Self::Deploy { piece, pos } => format!("{}@{}", piece, pos),
+            Self::Move { change } => change.clone(),
+            Self::Promote { change, upgrade_to } => format!("{}={}", change, upgrade_to),
+        }
+    }
+}
+
+impl Clock {
+    fn new(now: Instant) -> Self {
+        Self {
+            since: now,
+            paused: false,
+            spent: ZERO_SECS,
+        }
+    }
+}
+
 impl Game {
-    fn new(active_participants: ((UserId, UserId), (UserId, UserId))) -> Self {
+    fn new(
+        active_participants: ((UserId, UserId), (UserId, UserId)),
+        time_control: TimeControl,
+    ) -> Self {
         let now = Instant::now();
+        let base = time_control.base;
         Self {
             active_participants,
-            clock: ((now, false), (now, false)),
-            remaining_time: (
-                (GAME_DURATION, GAME_DURATION),
-                (GAME_DURATION, GAME_DURATION),
-            ),
+            clock: (Clock::new(now), Clock::new(now)),
+            remaining_time: ((base, base), (base, base)),
+            time_control,
+            resigned: None,
+            draw_offer: None,
+            draw_accepted: None,
+            moves: Vec::new(),
+            started: now,
+            date: SystemTime::now(),
+            awaiting: HashSet::new(),
             logic: ChessLogic::new(),
         }
     }
@@ -370,103 +1240,453 @@ impl Game {
         }
     }
 
-    fn refresh_clock(&mut self, board: bool) {
+    /// Returns `true` for the team of the first pair in `active_participants`
+    /// and `false` for the other one.
+    fn team(&self, user_id: &UserId) -> Option<bool> {
+        self.board_and_color(user_id)
+            .map(|(board, white)| board == white)
+    }
+
+    fn clock_mut(&mut self, board: bool) -> &mut Clock {
         let (c1, c2) = &mut self.clock;
-        let (c, _) = if board { c1 } else { c2 };
-        *c = Instant::now();
+        if board {
+            c1
+        } else {
+            c2
+        }
     }
 
-    fn extend_remaining_time(&mut self, board: bool, duration: Duration) {
+    fn remaining_time_mut(&mut self, board: bool, white: bool) -> &mut Duration {
         let ((r1, r2), (r3, r4)) = &mut self.remaining_time;
         let (rw, rb) = if board { (r1, r3) } else { (r4, r2) };
-        let r = if self.logic.get_white_active(board) {
+        if white {
             rw
         } else {
             rb
-        };
-        *r += duration;
+        }
+    }
+
+    fn refresh_clock(&mut self, board: bool) {
+        self.clock_mut(board).since = Instant::now();
+    }
+
+    /// Charges the active players for the time they spent so far.
+    fn update_clocks(&mut self) {
+        for board in [true, false] {
+            self.update_remaining_time(board);
+            self.refresh_clock(board);
+        }
+    }
+
+    fn extend_remaining_time(&mut self, board: bool, white: bool, duration: Duration) {
+        let r = self.remaining_time_mut(board, white);
+        *r = r.checked_add(duration).unwrap_or(*r);
     }
 
     fn update_remaining_time(&mut self, board: bool) {
-        let (c1, c2) = &mut self.clock;
-        let (c, p) = if board { c1 } else { c2 };
-        if *p {
+        let delay = match self.time_control.delay {
+            Delay::Simple { delay } => delay,
+            _ => ZERO_SECS,
+        };
+        let white = self.logic.get_white_active(board);
+        let c = self.clock_mut(board);
+        if c.paused {
             return;
         }
-        let ((r1, r2), (r3, r4)) = &mut self.remaining_time;
-        let (rw, rb) = if board { (r1, r3) } else { (r4, r2) };
-        let r = if self.logic.get_white_active(board) {
-            rw
+        let spent_before = c.spent;
+        c.spent += c.since.elapsed();
+        // With a simple delay, only the time spent beyond the delay counts.
+        let charged = c.spent.checked_sub(delay).unwrap_or(ZERO_SECS)
+            - spent_before.checked_sub(delay).unwrap_or(ZERO_SECS);
+        let r = self.remaining_time_mut(board, white);
+        *r = r.checked_sub(charged).unwrap_or(ZERO_SECS);
+    }
+
+    /// Charges the player for the time spent on their move. Fails if their
+    /// time ran out before the move, which is then refused, or if the clocks
+    /// are paused until every player reconnects.
+    fn start_move(&mut self, board: bool, white: bool) -> Result<()> {
+        if !self.awaiting.is_empty() {
+            return Err(Error::GamePaused);
+        }
+        self.update_remaining_time(board);
+        self.refresh_clock(board);
+        if *self.remaining_time_mut(board, white) == ZERO_SECS {
+            return Err(Error::OutOfTime);
+        }
+        Ok(())
+    }
+
+    /// Adds the increment and the Bronstein delay to the clock of the player
+    /// who just moved, and starts the turn of their opponent.
+    fn end_turn(&mut self, board: bool, white: bool) {
+        let c = self.clock_mut(board);
+        let spent = c.spent;
+        c.spent = ZERO_SECS;
+        let refund = match self.time_control.delay {
+            Delay::Bronstein { delay } => delay.min(spent),
+            _ => ZERO_SECS,
+        };
+        self.extend_remaining_time(board, white, self.time_control.increment + refund);
+        self.refresh_clock(board);
+    }
+
+    /// Returns the earliest point in time at which an active player's clock
+    /// runs out if they don't move.
+    fn deadline(&self) -> Option<Instant> {
+        let delay = match self.time_control.delay {
+            Delay::Simple { delay } => delay,
+            _ => ZERO_SECS,
+        };
+        let ((r1, r2), (r3, r4)) = self.remaining_time;
+        let (c1, c2) = &self.clock;
+        let b1 = if self.logic.get_white_active(true) {
+            r1
         } else {
-            rb
+            r3
+        };
+        let b2 = if self.logic.get_white_active(false) {
+            r4
+        } else {
+            r2
         };
-        *r = r.checked_sub(c.elapsed()).unwrap_or(ZERO_SECS);
+        vec![(c1, b1), (c2, b2)]
+            .into_iter()
+            .filter(|(c, _)| !c.paused)
+            .filter_map(|(c, r)| {
+                let delay = delay.checked_sub(c.spent).unwrap_or(ZERO_SECS);
+                c.since.checked_add(r.checked_add(delay)?)
+            })
+            .min()
     }
 
-    fn winner(&self) -> Winner {
+    /// Returns the result of the game if it has ended.
+    fn result(&self) -> Option<GameResult> {
+        let ((a, b), (c, d)) = self.active_participants;
         let ((r1, r2), (r3, r4)) = self.remaining_time;
-        if self.logic.get_white_active(true) && r1 == ZERO_SECS {
-            Winner::B1
+        if let Some(player) = self.draw_accepted {
+            return Some(GameResult {
+                player: Some(player),
+                ..GameResult::new(EndReason::DrawAgreement)
+            });
+        }
+        let (reason, winner) = if self.logic.get_white_active(true) && r1 == ZERO_SECS {
+            (EndReason::Flag, Winner::B1)
         } else if !self.logic.get_white_active(false) && r2 == ZERO_SECS {
-            Winner::W2
+            (EndReason::Flag, Winner::W2)
         } else if !self.logic.get_white_active(true) && r3 == ZERO_SECS {
-            Winner::W1
+            (EndReason::Flag, Winner::W1)
         } else if self.logic.get_white_active(false) && r4 == ZERO_SECS {
-            Winner::B2
+            (EndReason::Flag, Winner::B2)
+        } else if self.resigned.is_some() {
+            (EndReason::Resignation, self.logic.get_winner(true))
         } else {
-            self.logic.get_winner(true)
+            (EndReason::Checkmate, self.logic.get_winner(true))
+        };
+        // The losing player on the board where the game was decided.
+        let (winners, board, loser) = match winner {
+            Winner::W1 => ((a, b), 1, c),
+            Winner::B1 => ((c, d), 1, a),
+            Winner::W2 => ((c, d), 2, b),
+            Winner::B2 => ((a, b), 2, d),
+            Winner::P => return Some(GameResult::new(EndReason::Draw)),
+            _ => return None,
+        };
+        Some(GameResult {
+            winners: Some(winners),
+            reason,
+            board: Some(board),
+            player: self.resigned.or(Some(loser)),
+        })
+    }
+
+    /// Pauses both clocks until every active participant reconnects.
+    fn pause(&mut self) {
+        let ((a, b), (c, d)) = self.active_participants;
+        self.awaiting = vec![a, b, c, d].into_iter().collect();
+        self.clock_mut(true).paused = true;
+        self.clock_mut(false).paused = true;
+    }
+
+    /// Marks the user as reconnected. Returns `true` if this resumed the
+    /// clocks.
+    fn reconnect(&mut self, user_id: &UserId) -> bool {
+        if !self.awaiting.remove(user_id) || !self.awaiting.is_empty() {
+            return false;
+        }
+        for board in [true, false] {
+            self.clock_mut(board).paused = false;
+            self.refresh_clock(board);
+        }
+        true
+    }
+
+    /// Returns the number of moves made on the board so far.
+    pub fn ply(&self, board: bool) -> usize {
+        let board = if board { 1 } else { 2 };
+        self.moves.iter().filter(|m| m.board == board).count()
+    }
+
+    /// Succeeds if `ply` is not given or matches the number of moves made on
+    /// the user's board.
+    fn check_ply(&self, user_id: &UserId, ply: Option<usize>) -> Result<()> {
+        let (board, _) = self.board_and_color(user_id).ok_or(Error::NotParticipant)?;
+        match ply {
+            Some(ply) if ply != self.ply(board) => Err(Error::StalePosition),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies a move from the move log to the boards without touching the
+    /// clocks.
+    fn replay(&mut self, m: &Move) -> Result<()> {
+        let board = m.board == 1;
+        match &m.kind {
+            MoveKind::Deploy { piece, pos } => {
+                let parsed = utils::parse_piece(piece).ok_or(Error::InvalidNotation)?;
+                let (col, row) = utils::parse_pos(pos).ok_or(Error::InvalidNotation)?;
+                self.logic
+                    .deploy_piece(board, m.white, parsed, row, col)
+                    .or(Err(Error::IllegalMove))?;
+            }
+            MoveKind::Move { change } => {
+                let [i, j, i_new, j_new] =
+                    utils::parse_change(change).ok_or(Error::InvalidNotation)?;
+                self.logic
+                    .movemaker(board, i, j, i_new, j_new)
+                    .or(Err(Error::IllegalMove))?;
+            }
+            MoveKind::Promote { change, upgrade_to } => {
+                let [i, j, i_new, j_new] =
+                    utils::parse_change(change).ok_or(Error::InvalidNotation)?;
+                let parsed = utils::parse_piece(upgrade_to).ok_or(Error::InvalidNotation)?;
+                self.logic.set_promotion(board, parsed);
+                self.logic
+                    .movemaker(board, i, j, i_new, j_new)
+                    .or(Err(Error::IllegalMove))?;
+            }
         }
+        Ok(())
+    }
+
+    /// Appends a successful move to the move log.
+    fn record(&mut self, board: bool, white: bool, user_id: &UserId, kind: MoveKind) {
+        let remaining_time = (
+            *self.remaining_time_mut(board, true),
+            *self.remaining_time_mut(board, false),
+        );
+        // A move on either board withdraws the draw offer.
+        self.draw_offer = None;
+        self.moves.push(Move {
+            board: if board { 1 } else { 2 },
+            white,
+            user_id: *user_id,
+            notation: kind.notation(),
+            kind,
+            remaining_time,
+            timestamp: self.started.elapsed(),
+        });
     }
 
     fn resign(&mut self, user_id: &UserId) -> Result<()> {
-        let (b, w) = self.board_and_color(user_id).ok_or(Error::Error)?;
+        let (b, w) = self.board_and_color(user_id).ok_or(Error::NotParticipant)?;
         self.logic.resign(b, w);
+        self.resigned = Some(*user_id);
         Ok(())
     }
 
+    /// Offers a draw to the other team. If they already offered one, it is
+    /// accepted instead and `true` is returned.
+    fn offer_draw(&mut self, user_id: &UserId) -> Result<bool> {
+        match self.accept_draw(user_id) {
+            Err(Error::NoDrawOffer) => {
+                self.draw_offer = Some(*user_id);
+                Ok(false)
+            }
+            res => res.map(|_| true),
+        }
+    }
+
+    /// Accepts the draw offered by the other team.
+    fn accept_draw(&mut self, user_id: &UserId) -> Result<()> {
+        let team = self.team(user_id).ok_or(Error::NotParticipant)?;
+        match self.draw_offer {
+            Some(offer) if self.team(&offer) != Some(team) => {
+                self.draw_accepted = Some(*user_id);
+                Ok(())
+            }
+            _ => Err(Error::NoDrawOffer),
+        }
+    }
+
     fn deploy_piece(&mut self, user_id: &UserId, piece: &str, pos: &str) -> Result<()> {
-        let (b1, w) = self.board_and_color(user_id).ok_or(Error::Error)?;
-        let piece = utils::parse_piece(piece).ok_or(Error::Error)?;
-        let (col, row) = utils::parse_pos(&pos).ok_or(Error::Error)?;
-        self.update_remaining_time(b1);
-        self.refresh_clock(b1);
+        let (b1, w) = self.board_and_color(user_id).ok_or(Error::NotParticipant)?;
+        if self.logic.get_white_active(b1) != w {
+            return Err(Error::NotYourTurn);
+        }
+        let parsed = utils::parse_piece(piece).ok_or(Error::InvalidNotation)?;
+        let (col, row) = utils::parse_pos(&pos).ok_or(Error::InvalidNotation)?;
+        self.start_move(b1, w)?;
         self.logic
-            .deploy_piece(b1, w, piece, row, col)
-            .or(Err(Error::Error))?;
-        self.refresh_clock(b1);
+            .deploy_piece(b1, w, parsed, row, col)
+            .or(Err(Error::IllegalMove))?;
+        self.end_turn(b1, w);
+        let kind = MoveKind::Deploy {
+            piece: piece.to_owned(),
+            pos: pos.to_owned(),
+        };
+        self.record(b1, w, user_id, kind);
         Ok(())
     }
 
     fn move_piece(&mut self, user_id: &UserId, change: &str) -> Result<()> {
-        let (b1, w) = self.board_and_color(user_id).ok_or(Error::Error)?;
+        let (b1, w) = self.board_and_color(user_id).ok_or(Error::NotParticipant)?;
         if self.logic.get_white_active(b1) != w {
-            return Err(Error::Error);
+            return Err(Error::NotYourTurn);
         }
-        let [i, j, i_new, j_new] = utils::parse_change(&change.to_owned()).ok_or(Error::Error)?;
-        self.update_remaining_time(b1);
-        self.refresh_clock(b1);
+        let [i, j, i_new, j_new] =
+            utils::parse_change(&change.to_owned()).ok_or(Error::InvalidNotation)?;
+        self.start_move(b1, w)?;
         self.logic
             .movemaker(b1, i, j, i_new, j_new)
-            .or(Err(Error::Error))?;
-        self.refresh_clock(b1);
+            .or(Err(Error::IllegalMove))?;
+        self.end_turn(b1, w);
+        let kind = MoveKind::Move {
+            change: change.to_owned(),
+        };
+        self.record(b1, w, user_id, kind);
         Ok(())
     }
 
     fn promote_piece(&mut self, user_id: &UserId, change: &str, upgrade_to: &str) -> Result<()> {
-        let (b1, w) = self.board_and_color(user_id).ok_or(Error::Error)?;
+        let (b1, w) = self.board_and_color(user_id).ok_or(Error::NotParticipant)?;
         if self.logic.get_white_active(b1) != w {
-            return Err(Error::Error);
-        }
-        let [i, j, i_new, j_new] = utils::parse_change(&change.to_owned()).ok_or(Error::Error)?;
-        let upgrade_to = utils::parse_piece(&upgrade_to).ok_or(Error::Error)?;
-        self.logic.set_promotion(b1, upgrade_to);
-        self.extend_remaining_time(b1, PROMOTE_ADDED_TIME);
-        self.update_remaining_time(b1);
-        self.refresh_clock(b1);
+            return Err(Error::NotYourTurn);
+        }
+        let [i, j, i_new, j_new] =
+            utils::parse_change(&change.to_owned()).ok_or(Error::InvalidNotation)?;
+        let parsed = utils::parse_piece(&upgrade_to).ok_or(Error::InvalidNotation)?;
+        self.logic.set_promotion(b1, parsed);
+        self.start_move(b1, w)?;
         self.logic
             .movemaker(b1, i, j, i_new, j_new)
-            .or(Err(Error::Error))?;
-        self.refresh_clock(b1);
+            .or(Err(Error::IllegalMove))?;
+        self.end_turn(b1, w);
+        self.extend_remaining_time(b1, w, self.time_control.promotion_bonus);
+        let kind = MoveKind::Promote {
+            change: change.to_owned(),
+            upgrade_to: upgrade_to.to_owned(),
+        };
+        self.record(b1, w, user_id, kind);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_secs(300);
+
+    fn game(delay: Delay, increment: u64) -> Game {
+        let time_control = TimeControl {
+            base: BASE,
+            increment: Duration::from_secs(increment),
+            delay,
+            promotion_bonus: ZERO_SECS,
+        };
+        let players = (
+            (UserId::new(0), UserId::new(1)),
+            (UserId::new(2), UserId::new(3)),
+        );
+        Game::new(players, time_control)
+    }
+
+    /// Makes it look as if the white player of the first board has been
+    /// thinking for `secs` since their clock was last checked.
+    fn think(game: &mut Game, secs: u64) {
+        game.clock.0.since = Instant::now() - Duration::from_secs(secs);
+    }
+
+    fn remaining(game: &Game) -> Duration {
+        (game.remaining_time.0).0
+    }
+
+    /// Asserts that `actual` is no more than a tenth of a second past
+    /// `expected`, to allow for the time the test takes.
+    fn assert_about(actual: Duration, expected: Duration) {
+        let late = actual
+            .checked_sub(expected)
+            .or_else(|| expected.checked_sub(actual));
+        assert!(
+            late.is_some_and(|d| d < Duration::from_millis(100)),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn time_is_charged_without_a_delay() {
+        let mut g = game(Delay::None, 2);
+        think(&mut g, 3);
+        g.update_remaining_time(true);
+        assert_about(remaining(&g), BASE - secs(3));
+        g.end_turn(true, true);
+        assert_about(remaining(&g), BASE - secs(1));
+    }
+
+    #[test]
+    fn simple_delay_is_not_charged() {
+        let mut g = game(Delay::Simple { delay: secs(5) }, 0);
+        think(&mut g, 3);
+        g.update_remaining_time(true);
+        g.refresh_clock(true);
+        assert_eq!(remaining(&g), BASE);
+        // Only the time past the delay counts, however often it is checked.
+        think(&mut g, 4);
+        g.update_remaining_time(true);
+        assert_about(remaining(&g), BASE - secs(2));
+        g.end_turn(true, true);
+        assert_about(remaining(&g), BASE - secs(2));
+        assert_eq!(g.clock.0.spent, ZERO_SECS);
+    }
+
+    #[test]
+    fn bronstein_delay_is_refunded_up_to_the_delay() {
+        let mut g = game(Delay::Bronstein { delay: secs(5) }, 0);
+        think(&mut g, 3);
+        g.update_remaining_time(true);
+        assert_about(remaining(&g), BASE - secs(3));
+        g.end_turn(true, true);
+        assert_about(remaining(&g), BASE);
+        think(&mut g, 8);
+        g.update_remaining_time(true);
+        g.end_turn(true, true);
+        assert_about(remaining(&g), BASE - secs(3));
+    }
+
+    #[test]
+    fn deadline_includes_the_simple_delay() {
+        let mut g = game(Delay::Simple { delay: secs(5) }, 0);
+        let since = g.clock.0.since.min(g.clock.1.since);
+        assert_about(g.deadline().unwrap() - since, BASE + secs(5));
+        g.pause();
+        assert_eq!(g.deadline(), None);
+    }
+
+    #[test]
+    fn moves_are_refused_once_the_flag_fell() {
+        let mut g = game(Delay::None, 0);
+        (g.remaining_time.0).0 = secs(1);
+        think(&mut g, 2);
+        assert_eq!(g.start_move(true, true), Err(Error::OutOfTime));
+        let result = g.result().unwrap();
+        assert!(matches!(result.reason, EndReason::Flag));
+        assert_eq!(result.player, Some(UserId::new(0)));
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright (C) 2020  Kerem Çakırer
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{utils, Game, GameResult, GameState, Move, Msg, Series, Session};
+use crate::{
+    common::*,
+    config::Config,
+    data::{TimeControl, User, UserId},
+    sessions::Sessions,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::mpsc;
+
+const EXTENSION: &str = "json";
+/// How far event IDs skip ahead after a restart. Events which were sent after
+/// the last save are not saved, so this keeps their IDs from being given to
+/// other events.
+const EVENT_ID_GAP: usize = u32::MAX as usize;
+
+/// `Saved` is the state of a session as it is written to the data directory.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Saved {
+    id: SessionId,
+    users: Vec<SavedUser>,
+    #[serde(default)]
+    banned: Vec<String>,
+    #[serde(default)]
+    banned_tokens: Vec<PlayerToken>,
+    #[serde(default)]
+    spectators: Vec<SavedSpectator>,
+    participants: Vec<UserId>,
+    time_control: TimeControl,
+    #[serde(default)]
+    public: bool,
+    #[serde(default = "default_anonymous_spectators")]
+    anonymous_spectators: bool,
+    #[serde(default)]
+    auto_start: Duration,
+    #[serde(default)]
+    series: Option<Series>,
+    queue: VecDeque<((UserId, UserId), (UserId, UserId))>,
+    game_id: usize,
+    /// The ID of the last event which was sent.
+    #[serde(default)]
+    event_id: usize,
+    game: Option<SavedGame>,
+    result: Option<GameResult>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedUser {
+    auth_token: AuthToken,
+    player_token: PlayerToken,
+    user_id: UserId,
+    user: User,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedSpectator {
+    auth_token: AuthToken,
+    name: Option<String>,
+}
+
+fn default_anonymous_spectators() -> bool {
+    true
+}
+
+/// `SavedGame` is the state of a running game. The position on the boards is
+/// not saved, since it can be rebuilt by replaying the moves.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SavedGame {
+    active_participants: ((UserId, UserId), (UserId, UserId)),
+    remaining_time: ((Duration, Duration), (Duration, Duration)),
+    /// Time spent by the active player of each board on their current move.
+    #[serde(default)]
+    spent: (Duration, Duration),
+    time_control: TimeControl,
+    moves: Vec<Move>,
+    date: SystemTime,
+}
+
+impl Session {
+    /// Writes the state of the session to the data directory in the
+    /// background, if there is one.
+    pub(super) fn save(&mut self) {
+        let path = match self.path() {
+            Some(path) => path,
+            None => return,
+        };
+        // Charge the clocks first, so that the time spent on the current moves
+        // isn't given back after a restart.
+        self.game.map(Game::update_clocks);
+        let json = serde_json::to_vec(&self.to_saved()).unwrap();
+        self.saves += 1;
+        let save = self.saves;
+        let written = self.written.clone();
+        tokio::spawn(async move { save_file(&path, json, save, &written).await });
+    }
+
+    /// Removes the saved state of the session from the data directory, once
+    /// the saves in progress are written.
+    pub(super) async fn discard(&self) {
+        if let Some(path) = self.path() {
+            // Keep any pending save from bringing the file back.
+            *self.written.lock().await = usize::MAX;
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    /// Restores every session which was saved to the data directory. Clocks of
+    /// running games stay paused until all of their players reconnect.
+    pub fn load_all(sessions: &Sessions, config: &Arc<Config>) -> Vec<(Self, mpsc::Sender<Msg>)> {
+        let entries = match config.data_dir().map(fs::read_dir) {
+            Some(Ok(entries)) => entries,
+            _ => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+            .filter_map(|path| fs::read(path).ok())
+            .filter_map(|json| serde_json::from_slice::<Saved>(&json).ok())
+            .filter_map(|saved| Self::from_saved(sessions.clone(), config.clone(), saved))
+            .collect()
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        let dir = self.config.data_dir()?;
+        Some(dir.join(format!("{}.{}", self.id, EXTENSION)))
+    }
+
+    fn to_saved(&self) -> Saved {
+        let users = self
+            .user_ids
+            .iter()
+            .filter_map(|(auth_token, user_id)| {
+                Some(SavedUser {
+                    auth_token: auth_token.clone(),
+                    player_token: self.player_tokens.get(user_id)?.clone(),
+                    user_id: *user_id,
+                    user: self.users.get(user_id)?.clone(),
+                })
+            })
+            .collect();
+        let mut spectators: Vec<_> = self
+            .spectator_ids
+            .iter()
+            .map(|(auth_token, &i)| (i, auth_token))
+            .collect();
+        spectators.sort_by_key(|(i, _)| *i);
+        let spectators = spectators
+            .into_iter()
+            .map(|(i, auth_token)| SavedSpectator {
+                auth_token: auth_token.clone(),
+                name: self.spectators[i].clone(),
+            })
+            .collect();
+        let game = self.game.get().map(|g| SavedGame {
+            active_participants: g.active_participants,
+            remaining_time: g.remaining_time,
+            spent: (g.clock.0.spent, g.clock.1.spent),
+            time_control: g.time_control,
+            moves: g.moves.clone(),
+            date: g.date,
+        });
+        let result = match &self.game {
+            GameState::Ended { result, .. } => Some(result.clone()),
+            _ => None,
+        };
+        Saved {
+            id: self.id.clone(),
+            users,
+            banned: self.banned.iter().cloned().collect(),
+            banned_tokens: self.banned_tokens.iter().cloned().collect(),
+            spectators,
+            participants: self.participants.clone(),
+            time_control: self.time_control,
+            public: self.public,
+            anonymous_spectators: self.anonymous_spectators,
+            auto_start: self.auto_start,
+            series: self.series.clone(),
+            queue: self.queue.clone(),
+            game_id: self.game.id(),
+            event_id: self.event_id,
+            game,
+            result,
+        }
+    }
+
+    fn from_saved(
+        sessions: Sessions,
+        config: Arc<Config>,
+        saved: Saved,
+    ) -> Option<(Self, mpsc::Sender<Msg>)> {
+        let (mut session, tx) = Self::empty(sessions, config, saved.id);
+        for u in saved.users {
+            session.user_ids.insert(u.auth_token, u.user_id);
+            session.player_tokens.insert(u.user_id, u.player_token);
+            session.users.insert(u.user_id, u.user);
+        }
+        session.banned = saved
+            .banned
+            .iter()
+            .map(|name| utils::normalize_name(name).to_lowercase())
+            .collect();
+        session.banned_tokens = saved.banned_tokens.into_iter().collect();
+        for sp in saved.spectators {
+            session
+                .spectator_ids
+                .insert(sp.auth_token, session.spectators.len());
+            session.spectators.push(sp.name);
+        }
+        session.participants = saved.participants;
+        session.time_control = saved.time_control;
+        session.public = saved.public;
+        session.anonymous_spectators = saved.anonymous_spectators;
+        session.auto_start = saved.auto_start;
+        session.series = saved.series;
+        session.queue = saved.queue;
+        session.event_id = saved.event_id.saturating_add(EVENT_ID_GAP);
+        session.game = match (saved.game, saved.result) {
+            (Some(game), _) => GameState::Started {
+                id: saved.game_id,
+                game: game.restore()?,
+            },
+            (None, Some(result)) => GameState::Ended {
+                id: saved.game_id,
+                result,
+            },
+            _ => GameState::Starting,
+        };
+        // Nobody is connected to a restored session, so the owner has to come
+        // back in time to keep it.
+        session.reset_owner_presence();
+        Some((session, tx))
+    }
+}
+
+impl SavedGame {
+    fn restore(self) -> Option<Game> {
+        let mut game = Game::new(self.active_participants, self.time_control);
+        for m in &self.moves {
+            game.replay(m).ok()?;
+        }
+        let elapsed = self
+            .moves
+            .last()
+            .map_or(Duration::from_secs(0), |m| m.timestamp);
+        game.started = Instant::now()
+            .checked_sub(elapsed)
+            .unwrap_or_else(Instant::now);
+        game.date = self.date;
+        game.remaining_time = self.remaining_time;
+        game.clock.0.spent = self.spent.0;
+        game.clock.1.spent = self.spent.1;
+        game.moves = self.moves;
+        game.pause();
+        Some(game)
+    }
+}
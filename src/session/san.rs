@@ -0,0 +1,393 @@
+// Copyright (C) 2020  Kerem Çakırer
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::MoveKind;
+
+type Square = (usize, usize);
+
+const KNIGHT: [(isize, isize); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING: [(isize, isize); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// `Board` follows the position of one board of a game through its move log,
+/// so that the moves can be written in the standard algebraic notation (SAN).
+#[derive(Clone)]
+pub struct Board {
+    /// Pieces as written in FEN, indexed by rank and file starting from a1.
+    squares: [[Option<char>; 8]; 8],
+    /// The square a pawn skipped with the last move, if it moved two squares.
+    en_passant: Option<Square>,
+}
+
+impl Board {
+    pub fn new() -> Self {
+        let mut squares = [[None; 8]; 8];
+        for (file, piece) in "rnbqkbnr".chars().enumerate() {
+            squares[0][file] = Some(piece.to_ascii_uppercase());
+            squares[1][file] = Some('P');
+            squares[6][file] = Some('p');
+            squares[7][file] = Some(piece);
+        }
+        Self {
+            squares,
+            en_passant: None,
+        }
+    }
+
+    /// Makes the move and returns it in SAN. Drops are written as e.g. `N@f3`.
+    /// Returns `None` if the move doesn't fit the position.
+    pub fn play(&mut self, kind: &MoveKind, white: bool) -> Option<String> {
+        let mut san = match kind {
+            MoveKind::Deploy { piece, pos } => {
+                let letter = letter(piece)?;
+                let to = square(pos)?;
+                if self.get(to).is_some() {
+                    return None;
+                }
+                self.set(to, Some(colored(letter, white)));
+                self.en_passant = None;
+                format!("{}@{}", letter, name(to))
+            }
+            MoveKind::Move { change } => self.move_piece(change, None, white)?,
+            MoveKind::Promote { change, upgrade_to } => {
+                let letter = letter(upgrade_to)?;
+                self.move_piece(change, Some(colored(letter, white)), white)?
+            }
+        };
+        if self.in_check(!white) {
+            san.push('+');
+        }
+        Some(san)
+    }
+
+    fn move_piece(&mut self, change: &str, promotion: Option<char>, white: bool) -> Option<String> {
+        let from = square(change.get(0..2)?)?;
+        let to = square(change.get(2..4)?)?;
+        let piece = self.get(from)?;
+        if piece.is_ascii_uppercase() != white || !self.can_move(from, to) {
+            return None;
+        }
+        let kind = piece.to_ascii_uppercase();
+        let mut capture = self.get(to).is_some();
+        let san = if kind == 'K' && (from.1 as isize - to.1 as isize).abs() == 2 {
+            let (rook_from, rook_to) = if to.1 > from.1 { (7, 5) } else { (0, 3) };
+            let rook = self.get((from.0, rook_from));
+            self.set((from.0, rook_from), None);
+            self.set((from.0, rook_to), rook);
+            if to.1 > from.1 { "O-O" } else { "O-O-O" }.to_owned()
+        } else if kind == 'P' {
+            if from.1 != to.1 && !capture && self.en_passant == Some(to) {
+                self.set((from.0, to.1), None);
+                capture = true;
+            }
+            let mut san = if capture {
+                format!("{}x{}", file(from), name(to))
+            } else {
+                name(to)
+            };
+            if let Some(promotion) = promotion {
+                san.push('=');
+                san.push(promotion.to_ascii_uppercase());
+            }
+            san
+        } else {
+            format!(
+                "{}{}{}{}",
+                kind,
+                self.disambiguation(piece, from, to),
+                if capture { "x" } else { "" },
+                name(to)
+            )
+        };
+        self.en_passant = if kind == 'P' && (from.0 as isize - to.0 as isize).abs() == 2 {
+            Some(((from.0 + to.0) / 2, from.1))
+        } else {
+            None
+        };
+        self.set(from, None);
+        self.set(to, Some(promotion.unwrap_or(piece)));
+        Some(san)
+    }
+
+    /// Returns `true` if the piece on `from` may move to `to`, leaving aside
+    /// whether its king is left in check.
+    fn can_move(&self, from: Square, to: Square) -> bool {
+        let piece = match self.get(from) {
+            Some(piece) => piece,
+            None => return false,
+        };
+        let white = piece.is_ascii_uppercase();
+        if self
+            .get(to)
+            .is_some_and(|p| p.is_ascii_uppercase() == white)
+        {
+            return false;
+        }
+        let dr = to.0 as isize - from.0 as isize;
+        let df = to.1 as isize - from.1 as isize;
+        let (forward, start) = if white { (1, 1) } else { (-1, 6) };
+        match piece.to_ascii_uppercase() {
+            'P' if df == 0 => {
+                self.get(to).is_none()
+                    && (dr == forward
+                        || dr == 2 * forward && from.0 == start && self.is_clear(from, to))
+            }
+            'P' => {
+                self.reaches(from, to) && (self.get(to).is_some() || self.en_passant == Some(to))
+            }
+            // Castling, which the rules of the board have already allowed.
+            'K' if dr == 0 && df.abs() == 2 => true,
+            _ => self.reaches(from, to),
+        }
+    }
+
+    /// Returns what has to follow the piece letter so that only the piece on
+    /// `from` can be meant: its file, its rank or both.
+    fn disambiguation(&self, piece: char, from: Square, to: Square) -> String {
+        let white = piece.is_ascii_uppercase();
+        let others: Vec<_> = self
+            .find(|p| p == piece)
+            .filter(|&sq| sq != from && self.reaches(sq, to))
+            .filter(|&sq| {
+                let mut after = self.clone();
+                after.set(to, after.get(sq));
+                after.set(sq, None);
+                !after.in_check(white)
+            })
+            .collect();
+        if others.is_empty() {
+            String::new()
+        } else if others.iter().all(|sq| sq.1 != from.1) {
+            file(from).to_string()
+        } else if others.iter().all(|sq| sq.0 != from.0) {
+            rank(from).to_string()
+        } else {
+            name(from)
+        }
+    }
+
+    /// Returns `true` if the king of the given color is attacked.
+    fn in_check(&self, white: bool) -> bool {
+        let king = colored('K', white);
+        match self.find(move |p| p == king).next() {
+            Some(sq) => self
+                .find(|p| p.is_ascii_uppercase() != white)
+                .any(|attacker| self.reaches(attacker, sq)),
+            None => false,
+        }
+    }
+
+    /// Returns `true` if the piece on `from` attacks `to`.
+    fn reaches(&self, from: Square, to: Square) -> bool {
+        let piece = match self.get(from) {
+            Some(piece) => piece,
+            None => return false,
+        };
+        let dr = to.0 as isize - from.0 as isize;
+        let df = to.1 as isize - from.1 as isize;
+        match piece.to_ascii_uppercase() {
+            'P' => dr == if piece.is_ascii_uppercase() { 1 } else { -1 } && df.abs() == 1,
+            'N' => KNIGHT.contains(&(dr, df)),
+            'K' => KING.contains(&(dr, df)),
+            'B' => dr.abs() == df.abs() && self.is_clear(from, to),
+            'R' => (dr == 0 || df == 0) && self.is_clear(from, to),
+            'Q' => (dr.abs() == df.abs() || dr == 0 || df == 0) && self.is_clear(from, to),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the squares between `from` and `to`, which are on
+    /// the same line, are empty.
+    fn is_clear(&self, from: Square, to: Square) -> bool {
+        if from == to {
+            return false;
+        }
+        let dr = (to.0 as isize - from.0 as isize).signum();
+        let df = (to.1 as isize - from.1 as isize).signum();
+        let (mut r, mut f) = (from.0 as isize + dr, from.1 as isize + df);
+        while (r, f) != (to.0 as isize, to.1 as isize) {
+            if self.squares[r as usize][f as usize].is_some() {
+                return false;
+            }
+            r += dr;
+            f += df;
+        }
+        true
+    }
+
+    /// Returns the squares holding pieces which match the predicate.
+    fn find<'a>(&'a self, pred: impl Fn(char) -> bool + 'a) -> impl Iterator<Item = Square> + 'a {
+        (0..8)
+            .flat_map(|r| (0..8).map(move |f| (r, f)))
+            .filter(move |&sq| self.get(sq).is_some_and(&pred))
+    }
+
+    fn get(&self, (r, f): Square) -> Option<char> {
+        self.squares[r][f]
+    }
+
+    fn set(&mut self, (r, f): Square, piece: Option<char>) {
+        self.squares[r][f] = piece;
+    }
+}
+
+/// Parses a square such as `e4`.
+fn square(s: &str) -> Option<Square> {
+    let mut chars = s.chars();
+    let f = chars.next().filter(|c| ('a'..='h').contains(c))?;
+    let r = chars.next().filter(|c| ('1'..='8').contains(c))?;
+    if chars.next().is_some() {
+        return None;
+    }
+    Some((r as usize - '1' as usize, f as usize - 'a' as usize))
+}
+
+fn name(sq: Square) -> String {
+    format!("{}{}", file(sq), rank(sq))
+}
+
+fn file((_, f): Square) -> char {
+    (b'a' + f as u8) as char
+}
+
+fn rank((r, _): Square) -> char {
+    (b'1' + r as u8) as char
+}
+
+/// Returns the upper case letter of a piece. Promoted pieces, which start
+/// with a `U`, are written as the piece they became.
+fn letter(piece: &str) -> Option<char> {
+    piece
+        .chars()
+        .last()
+        .map(|c| c.to_ascii_uppercase())
+        .filter(|c| "PNBRQK".contains(*c))
+}
+
+fn colored(letter: char, white: bool) -> char {
+    if white {
+        letter
+    } else {
+        letter.to_ascii_lowercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mv(change: &str) -> MoveKind {
+        MoveKind::Move {
+            change: change.to_owned(),
+        }
+    }
+
+    /// Plays the moves, white first, and returns them in SAN.
+    fn play(moves: &[MoveKind]) -> Vec<String> {
+        let mut board = Board::new();
+        moves
+            .iter()
+            .enumerate()
+            .map(|(i, m)| board.play(m, i % 2 == 0).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn pawn_and_piece_moves() {
+        let moves = [mv("e2e4"), mv("d7d5"), mv("e4d5"), mv("d8d5"), mv("b1c3")];
+        assert_eq!(play(&moves), ["e4", "d5", "exd5", "Qxd5", "Nc3"]);
+    }
+
+    #[test]
+    fn checks_castling_and_en_passant() {
+        let moves = [
+            mv("e2e4"),
+            mv("f7f5"),
+            mv("e4e5"),
+            mv("d7d5"),
+            mv("e5d6"),
+            mv("g8f6"),
+            mv("f1b5"),
+            mv("c7c6"),
+            mv("g1f3"),
+            mv("e7e6"),
+            mv("e1g1"),
+        ];
+        assert_eq!(
+            play(&moves),
+            ["e4", "f5", "e5", "d5", "exd6", "Nf6", "Bb5+", "c6", "Nf3", "e6", "O-O"]
+        );
+    }
+
+    /// Returns a board with only the given pieces on it.
+    fn board(pieces: &[(&str, char)]) -> Board {
+        let mut board = Board {
+            squares: [[None; 8]; 8],
+            en_passant: None,
+        };
+        for (sq, piece) in pieces {
+            board.set(square(sq).unwrap(), Some(*piece));
+        }
+        board
+    }
+
+    #[test]
+    fn ambiguous_moves_name_the_file_or_rank() {
+        let kings = [("e1", 'K'), ("h8", 'k')];
+        let mut b = board(&[kings[0], kings[1], ("b1", 'N'), ("f3", 'N')]);
+        assert_eq!(b.play(&mv("b1d2"), true).unwrap(), "Nbd2");
+        let mut b = board(&[kings[0], kings[1], ("a1", 'R'), ("a5", 'R')]);
+        assert_eq!(b.play(&mv("a1a3"), true).unwrap(), "R1a3");
+        let mut b = board(&[kings[0], kings[1], ("a1", 'Q'), ("a3", 'Q'), ("c1", 'Q')]);
+        assert_eq!(b.play(&mv("a1b2"), true).unwrap(), "Qa1b2+");
+        // The knight on e2 can't move, since it shields its king.
+        let mut b = board(&[kings[0], ("a8", 'k'), ("e8", 'r'), ("e2", 'N'), ("g2", 'N')]);
+        assert_eq!(b.play(&mv("g2f4"), true).unwrap(), "Nf4");
+    }
+
+    #[test]
+    fn drops_and_promotions() {
+        let mut b = Board::new();
+        let drop = MoveKind::Deploy {
+            piece: "n".to_owned(),
+            pos: "d3".to_owned(),
+        };
+        assert_eq!(b.play(&drop, false).unwrap(), "N@d3+");
+        let mut b = board(&[("e1", 'K'), ("e8", 'k'), ("a7", 'P'), ("b8", 'n')]);
+        let promote = |change: &str| MoveKind::Promote {
+            change: change.to_owned(),
+            upgrade_to: "UQ".to_owned(),
+        };
+        assert_eq!(b.play(&promote("a7b8"), true).unwrap(), "axb8=Q+");
+        assert_eq!(b.play(&mv("e3e4"), true), None);
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright (C) 2020  Kerem Çakırer
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+use super::{EndReason, GameResult};
+use crate::data::UserId;
+use serde::{Deserialize, Serialize};
+
+type Pairing = ((UserId, UserId), (UserId, UserId));
+
+/// The longest series which can be played.
+const MAX_BEST_OF: usize = 99;
+
+/// `Series` is a best-of-N match between two fixed teams.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Series {
+    /// The teams, written as ((white, black), (black, white)) for the first
+    /// game.
+    pub teams: Pairing,
+    pub best_of: usize,
+    /// Games won by each team.
+    pub score: (usize, usize),
+    /// Games which counted towards the series, including draws.
+    pub played: usize,
+}
+
+impl Series {
+    pub fn new(teams: Pairing, best_of: usize) -> Self {
+        Self {
+            teams,
+            best_of,
+            score: (0, 0),
+            played: 0,
+        }
+    }
+
+    /// Returns the players of the `n`th game, counting from 0. The teams take
+    /// turns playing white, and the players of a team take turns on each
+    /// board.
+    pub fn pairing(&self, n: usize) -> Pairing {
+        let ((a, b), (c, d)) = self.teams;
+        match n % 4 {
+            0 => ((a, b), (c, d)),
+            1 => ((c, d), (a, b)),
+            2 => ((d, c), (b, a)),
+            _ => ((b, a), (d, c)),
+        }
+    }
+
+    /// Returns `true` if a series can be `best_of` games long. It takes an odd
+    /// number of games, so that one team always wins.
+    pub fn is_valid_length(best_of: usize) -> bool {
+        best_of % 2 == 1 && best_of <= MAX_BEST_OF
+    }
+
+    /// Returns the players of the next game, or `None` if the series is over.
+    /// A game which didn't count is played again.
+    pub fn next_pairing(&self) -> Option<Pairing> {
+        if self.is_over() {
+            None
+        } else {
+            Some(self.pairing(self.played))
+        }
+    }
+
+    /// Returns the team which won the series, if one has clinched it.
+    pub fn winners(&self) -> Option<(UserId, UserId)> {
+        let ((a, b), (c, d)) = self.teams;
+        if 2 * self.score.0 > self.best_of {
+            Some((a, b))
+        } else if 2 * self.score.1 > self.best_of {
+            Some((c, d))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.winners().is_some() || self.played >= self.best_of
+    }
+
+    /// Returns `true` if `players` are the two teams of the series, in any
+    /// seats.
+    pub fn is_between(&self, players: &Pairing) -> bool {
+        let same = |(a, b): (UserId, UserId), (c, d): (UserId, UserId)| {
+            (a == c && b == d) || (a == d && b == c)
+        };
+        let ((t1, t2), (p1, p2)) = (self.teams, *players);
+        (same(t1, p1) && same(t2, p2)) || (same(t1, p2) && same(t2, p1))
+    }
+
+    /// Counts the result of a game towards the series. Returns `false` if the
+    /// game didn't count, because it wasn't played between the two teams or
+    /// didn't finish.
+    pub fn record(&mut self, players: &Pairing, result: &GameResult) -> bool {
+        if self.is_over() || !self.is_between(players) {
+            return false;
+        }
+        match result.reason {
+            EndReason::Abort | EndReason::Termination => return false,
+            _ => (),
+        }
+        self.played += 1;
+        let ((a, b), _) = self.teams;
+        match result.winners {
+            Some((w, _)) if w == a || w == b => self.score.0 += 1,
+            Some(_) => self.score.1 += 1,
+            None => (),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn teams() -> Pairing {
+        let u = UserId::new;
+        ((u(1), u(2)), (u(3), u(4)))
+    }
+
+    fn won_by(winners: Option<(UserId, UserId)>) -> GameResult {
+        let mut result = GameResult::new(EndReason::Checkmate);
+        result.winners = winners;
+        result
+    }
+
+    #[test]
+    fn pairings_rotate_colors_and_boards() {
+        let series = Series::new(teams(), 5);
+        let u = UserId::new;
+        assert_eq!(series.pairing(0), ((u(1), u(2)), (u(3), u(4))));
+        assert_eq!(series.pairing(1), ((u(3), u(4)), (u(1), u(2))));
+        assert_eq!(series.pairing(2), ((u(4), u(3)), (u(2), u(1))));
+        assert_eq!(series.pairing(3), ((u(2), u(1)), (u(4), u(3))));
+        assert_eq!(series.pairing(4), series.pairing(0));
+        for n in 0..4 {
+            assert!(series.is_between(&series.pairing(n)));
+        }
+    }
+
+    #[test]
+    fn only_finished_games_between_the_teams_count() {
+        let mut series = Series::new(teams(), 3);
+        let ((a, b), (c, d)) = teams();
+        assert!(!series.record(&teams(), &GameResult::new(EndReason::Abort)));
+        assert!(!series.record(&((a, c), (b, d)), &won_by(Some((a, c)))));
+        assert_eq!(series.played, 0);
+        assert_eq!(series.next_pairing(), Some(series.pairing(0)));
+        assert!(series.record(&teams(), &GameResult::new(EndReason::Draw)));
+        assert!(series.record(&series.pairing(1), &won_by(Some((d, c)))));
+        assert_eq!(series.score, (0, 1));
+        assert_eq!(series.played, 2);
+        assert_eq!(series.next_pairing(), Some(series.pairing(2)));
+    }
+
+    #[test]
+    fn a_team_wins_once_it_has_a_majority() {
+        let mut series = Series::new(teams(), 3);
+        let ((a, b), (c, d)) = teams();
+        assert!(series.record(&teams(), &won_by(Some((a, b)))));
+        assert_eq!(series.winners(), None);
+        assert!(series.record(&series.pairing(1), &won_by(Some((c, d)))));
+        assert_eq!(series.winners(), None);
+        assert!(series.record(&series.pairing(2), &won_by(Some((b, a)))));
+        assert_eq!(series.winners(), Some((a, b)));
+        assert!(series.is_over());
+        assert_eq!(series.next_pairing(), None);
+        assert!(!series.record(&series.pairing(3), &won_by(Some((c, d)))));
+        assert_eq!(series.score, (2, 1));
+    }
+
+    #[test]
+    fn series_have_an_odd_number_of_games() {
+        assert!(!Series::is_valid_length(0));
+        assert!(Series::is_valid_length(1));
+        assert!(!Series::is_valid_length(2));
+        assert!(Series::is_valid_length(MAX_BEST_OF));
+        assert!(!Series::is_valid_length(MAX_BEST_OF + 2));
+    }
+}
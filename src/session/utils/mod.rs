@@ -14,17 +14,30 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 mod pairings;
+mod schedule;
 
 use bughouse_rs::logic::board::Piece;
-use std::collections::VecDeque;
-use std::iter::FromIterator;
+use std::{collections::VecDeque, time::Duration};
 
 pub use bughouse_rs::parse::parser::parse as parse_change;
 
+/// The longest countdown before the next game starts on its own.
+const MAX_AUTO_START: Duration = Duration::from_secs(60 * 60);
+
 pub fn is_valid_user_name(name: &str) -> bool {
     !name.is_empty() && name.chars().all(|c| c.is_alphabetic() || c.is_whitespace())
 }
 
+/// Trims the name and collapses the whitespace in it, so that names which only
+/// differ in spacing are the same.
+pub fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn is_valid_auto_start(auto_start: Duration) -> bool {
+    auto_start <= MAX_AUTO_START
+}
+
 pub fn parse_piece(s: &str) -> Option<Piece> {
     match s {
         "b" => Some(Piece::b),
@@ -64,35 +77,7 @@ pub fn parse_pos(s: &str) -> Option<(usize, usize)> {
     }
 }
 
-pub fn create_pairings(n: u8) -> VecDeque<((u8, u8), (u8, u8))> {
-    if n == 4 {
-        VecDeque::from_iter(vec![
-            ((1, 2), (3, 4)),
-            ((1, 2), (4, 3)),
-            ((2, 1), (3, 4)),
-            ((2, 1), (4, 3)),
-            ((1, 3), (2, 4)),
-            ((1, 3), (4, 2)),
-            ((3, 1), (2, 4)),
-            ((3, 1), (4, 2)),
-            ((2, 3), (1, 4)),
-            ((2, 3), (4, 1)),
-            ((3, 2), (1, 4)),
-            ((3, 2), (4, 1)),
-            ((1, 4), (2, 3)),
-            ((1, 4), (3, 2)),
-            ((4, 1), (2, 3)),
-            ((4, 1), (3, 2)),
-            ((2, 4), (1, 3)),
-            ((2, 4), (3, 1)),
-            ((4, 2), (1, 3)),
-            ((4, 2), (3, 1)),
-            ((3, 4), (1, 2)),
-            ((3, 4), (2, 1)),
-            ((4, 3), (1, 2)),
-            ((4, 3), (2, 1)),
-        ])
-    } else {
-        pairings::create_pairings(4)
-    }
+/// Creates a fair schedule of games for `n` participants, numbered from 1.
+pub fn create_pairings(n: u8) -> VecDeque<schedule::Pairing> {
+    schedule::create_schedule(n as usize)
 }
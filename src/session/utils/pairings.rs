@@ -282,3 +282,56 @@ pub fn print_v(x: &VecDeque<((u8, u8), (u8, u8))>) {
         println!("]");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn players(n: usize) -> Vec<u8> {
+        (1..=n as u8).collect()
+    }
+
+    #[test]
+    fn combinations_are_ordered_pairs() {
+        for n in 2..=9 {
+            let pairs = combinations(&mut players(n), 2);
+            assert_eq!(pairs.len(), n * (n - 1));
+            let unique: HashSet<_> = pairs.iter().cloned().collect();
+            assert_eq!(unique.len(), pairs.len());
+            for pair in &pairs {
+                assert_eq!(pair.len(), 2);
+                assert_ne!(pair[0], pair[1]);
+                assert!(pair.iter().all(|&x| x >= 1 && x as usize <= n));
+            }
+        }
+    }
+
+    #[test]
+    fn rm_ordering_keeps_each_team_once() {
+        for n in 2..=9 {
+            let teams = rm_ordering(&mut combinations(&mut players(n), 2));
+            assert_eq!(teams.len(), n * (n - 1) / 2);
+            let unique: HashSet<_> = teams
+                .iter()
+                .map(|t| (t[0].min(t[1]), t[0].max(t[1])))
+                .collect();
+            assert_eq!(unique.len(), teams.len());
+        }
+    }
+
+    #[test]
+    fn create_pairings_covers_every_game_once() {
+        for n in 4..=9 {
+            let pairings = create_pairings(n);
+            assert_eq!(pairings.len(), n * (n - 1) * (n - 2) * (n - 3));
+            let unique: HashSet<_> = pairings.iter().cloned().collect();
+            assert_eq!(unique.len(), pairings.len());
+            for &((a, b), (c, d)) in &pairings {
+                let distinct: HashSet<_> = vec![a, b, c, d].into_iter().collect();
+                assert_eq!(distinct.len(), 4);
+                assert!(distinct.iter().all(|&x| x >= 1 && x as usize <= n));
+            }
+        }
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright (C) 2020  Kerem Çakırer
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::pairings;
+use std::collections::VecDeque;
+
+/// `Pairing` is a game between two teams, written as
+/// ((white, black), (black, white)). Players are numbered from 1.
+pub type Pairing = ((u8, u8), (u8, u8));
+
+/// Creates a schedule for `n` participants which is fair over its full length:
+/// everyone plays the same number of games and sits out in turn, and partners,
+/// opponents, colors and boards are spread as evenly as possible.
+pub fn create_schedule(n: usize) -> VecDeque<Pairing> {
+    if n < 4 {
+        return VecDeque::new();
+    }
+    let candidates = pairings::create_pairings(n);
+    let len = schedule_len(n);
+    let mut stats = Stats::new(n);
+    let mut schedule = VecDeque::with_capacity(len);
+    for _ in 0..len {
+        // The candidates are always the same, so this can't fail.
+        let next = *candidates.iter().min_by_key(|p| stats.cost(p)).unwrap();
+        stats.record(&next);
+        schedule.push_back(next);
+    }
+    balance_seats(&mut schedule, n);
+    schedule
+}
+
+/// Turns the games around so that everyone plays both colors and both boards
+/// about equally often. Turning a game around keeps the teams and the
+/// opponents.
+fn balance_seats(schedule: &mut VecDeque<Pairing>, n: usize) {
+    // Swapping the teams swaps the colors, swapping the boards keeps them.
+    balance(schedule, n, colors, |&((a, b), (c, d))| ((c, d), (a, b)));
+    balance(schedule, n, boards, |&((a, b), (c, d))| ((d, c), (b, a)));
+}
+
+/// Counts white as 1 and black as -1 for each player in the pairing.
+fn colors(pairing: &Pairing) -> [(usize, isize); 4] {
+    let [a, b, c, d] = indices(pairing);
+    [(a, 1), (b, -1), (c, -1), (d, 1)]
+}
+
+/// Counts board 1 as 1 and board 2 as -1 for each player in the pairing.
+fn boards(pairing: &Pairing) -> [(usize, isize); 4] {
+    let [a, b, c, d] = indices(pairing);
+    [(a, 1), (b, -1), (c, 1), (d, -1)]
+}
+
+/// Flips games until every player's `side` adds up to zero, or no flip of
+/// one or two games gets any closer to that. `flip` must negate `side`.
+fn balance(
+    schedule: &mut VecDeque<Pairing>,
+    n: usize,
+    side: fn(&Pairing) -> [(usize, isize); 4],
+    flip: fn(&Pairing) -> Pairing,
+) {
+    let mut sums = vec![0; n];
+    for pairing in schedule.iter() {
+        apply(&mut sums, side(pairing), 1);
+    }
+    // Returns how much flipping the pairing changes the sum of squares.
+    let gain = |sums: &[isize], pairing: &Pairing| {
+        side(pairing)
+            .iter()
+            .map(|&(x, v)| (sums[x] - 2 * v).pow(2) - sums[x].pow(2))
+            .sum::<isize>()
+    };
+    loop {
+        let single = (0..schedule.len()).find(|&i| gain(&sums, &schedule[i]) < 0);
+        let flips = match single {
+            Some(i) => vec![i],
+            None => {
+                let pair = (0..schedule.len()).find_map(|i| {
+                    let mut flipped = sums.clone();
+                    apply(&mut flipped, side(&schedule[i]), -2);
+                    let first = gain(&sums, &schedule[i]);
+                    (i + 1..schedule.len())
+                        .find(|&j| first + gain(&flipped, &schedule[j]) < 0)
+                        .map(|j| vec![i, j])
+                });
+                match pair {
+                    Some(flips) => flips,
+                    None => return,
+                }
+            }
+        };
+        for i in flips {
+            apply(&mut sums, side(&schedule[i]), -2);
+            schedule[i] = flip(&schedule[i]);
+        }
+    }
+}
+
+fn apply(sums: &mut [isize], side: [(usize, isize); 4], factor: isize) {
+    for &(x, v) in &side {
+        sums[x] += factor * v;
+    }
+}
+
+/// Returns the smallest number of games after which every player has played
+/// equally often, every pair of players could have partnered equally often,
+/// and every player could have taken each seat equally often.
+fn schedule_len(n: usize) -> usize {
+    let pairs = n * (n - 1) / 2;
+    let mut len = lcm(n / gcd(n, 4), pairs / gcd(pairs, 2));
+    // Each player has four seats: white and black on both boards.
+    while (4 * len / n) % 4 != 0 {
+        len *= 2;
+    }
+    len
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    a / gcd(a, b) * b
+}
+
+/// `Stats` counts how often each player played, partnered or faced each other
+/// player so far, and how many more games they played as white and on
+/// board 1.
+struct Stats {
+    played: Vec<usize>,
+    partners: Vec<Vec<usize>>,
+    opponents: Vec<Vec<usize>>,
+    colors: Vec<isize>,
+    boards: Vec<isize>,
+}
+
+impl Stats {
+    fn new(n: usize) -> Self {
+        Self {
+            played: vec![0; n],
+            partners: vec![vec![0; n]; n],
+            opponents: vec![vec![0; n]; n],
+            colors: vec![0; n],
+            boards: vec![0; n],
+        }
+    }
+
+    /// Returns how much the pairing would unbalance the schedule. The number
+    /// of games played matters most, the opponents matter least.
+    fn cost(&self, pairing: &Pairing) -> (usize, usize, isize, usize) {
+        let [a, b, c, d] = indices(pairing);
+        let played = self.played[a] + self.played[b] + self.played[c] + self.played[d];
+        let partners = self.partners[a][b] + self.partners[c][d];
+        let opponents = self.opponents[a][c] + self.opponents[b][d];
+        let seats = colors(pairing)
+            .iter()
+            .map(|&(x, v)| v * self.colors[x])
+            .chain(boards(pairing).iter().map(|&(x, v)| v * self.boards[x]))
+            .sum::<isize>();
+        (played, partners, seats, opponents)
+    }
+
+    fn record(&mut self, pairing: &Pairing) {
+        let [a, b, c, d] = indices(pairing);
+        for &(x, y) in &[(a, b), (c, d)] {
+            self.partners[x][y] += 1;
+            self.partners[y][x] += 1;
+        }
+        for &(x, y) in &[(a, c), (b, d)] {
+            self.opponents[x][y] += 1;
+            self.opponents[y][x] += 1;
+        }
+        for &x in &[a, b, c, d] {
+            self.played[x] += 1;
+        }
+        apply(&mut self.colors, colors(pairing), 1);
+        apply(&mut self.boards, boards(pairing), 1);
+    }
+}
+
+fn indices(&((a, b), (c, d)): &Pairing) -> [usize; 4] {
+    [
+        a as usize - 1,
+        b as usize - 1,
+        c as usize - 1,
+        d as usize - 1,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(n: usize) -> (VecDeque<Pairing>, Stats) {
+        let schedule = create_schedule(n);
+        let mut stats = Stats::new(n);
+        schedule.iter().for_each(|p| stats.record(p));
+        (schedule, stats)
+    }
+
+    fn spread(counts: &Vec<Vec<usize>>) -> usize {
+        let others = || {
+            counts
+                .iter()
+                .enumerate()
+                .flat_map(|(x, row)| row.iter().enumerate().filter(move |(y, _)| x != *y))
+                .map(|(_, &count)| count)
+        };
+        others().max().unwrap() - others().min().unwrap()
+    }
+
+    #[test]
+    fn too_few_participants() {
+        for n in 0..4 {
+            assert!(create_schedule(n).is_empty());
+        }
+    }
+
+    #[test]
+    fn schedule_len_is_fair() {
+        for n in 4..=16 {
+            let len = schedule_len(n);
+            let pairs = n * (n - 1) / 2;
+            assert_eq!(4 * len % n, 0, "n = {}", n);
+            assert_eq!(2 * len % pairs, 0, "n = {}", n);
+            assert_eq!(4 * len / n % 4, 0, "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn pairings_are_valid() {
+        for n in 4..=9 {
+            let (schedule, _) = stats(n);
+            assert_eq!(schedule.len(), schedule_len(n));
+            for pairing in &schedule {
+                let mut players = indices(pairing);
+                assert!(players.iter().all(|&x| x < n), "{:?}", pairing);
+                players.sort();
+                players.windows(2).for_each(|w| assert_ne!(w[0], w[1]));
+            }
+        }
+    }
+
+    #[test]
+    fn everyone_plays_equally_often() {
+        for n in 4..=9 {
+            let (_, stats) = stats(n);
+            let games = 4 * schedule_len(n) / n;
+            assert!(stats.played.iter().all(|&p| p == games), "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn everyone_sits_out_in_turn() {
+        for n in 5..=9 {
+            let (schedule, _) = stats(n);
+            // Nobody sits out twice before everyone else sat out once.
+            let mut rest = vec![0; n];
+            for pairing in &schedule {
+                let players = indices(pairing);
+                (0..n)
+                    .filter(|x| !players.contains(x))
+                    .for_each(|x| rest[x] += 1);
+                let (min, max) = (rest.iter().min().unwrap(), rest.iter().max().unwrap());
+                assert!(max - min <= 1, "n = {}, {:?}", n, rest);
+            }
+        }
+    }
+
+    #[test]
+    fn partners_and_opponents_are_balanced() {
+        for n in 4..=9 {
+            let (_, stats) = stats(n);
+            assert!(spread(&stats.partners) <= 1, "n = {}", n);
+            assert!(spread(&stats.opponents) <= 2, "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn colors_and_boards_are_balanced() {
+        for n in 4..=9 {
+            let (_, stats) = stats(n);
+            // At most one game more as white or on board 1 than the other way
+            // around.
+            assert!(stats.colors.iter().all(|c| c.abs() <= 2), "n = {}", n);
+            assert!(stats.boards.iter().all(|b| b.abs() <= 2), "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn turning_games_around_keeps_teams_and_opponents() {
+        for n in 4..=9 {
+            let schedule = create_schedule(n);
+            let mut plain = Stats::new(n);
+            let mut turned = Stats::new(n);
+            for &((a, b), (c, d)) in &schedule {
+                plain.record(&((a, b), (c, d)));
+                turned.record(&((c, d), (a, b)));
+                turned.record(&((d, c), (b, a)));
+                turned.record(&((b, a), (d, c)));
+            }
+            for x in 0..n {
+                for y in 0..n {
+                    assert_eq!(3 * plain.partners[x][y], turned.partners[x][y]);
+                    assert_eq!(3 * plain.opponents[x][y], turned.opponents[x][y]);
+                }
+            }
+        }
+    }
+}
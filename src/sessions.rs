@@ -16,10 +16,22 @@
 use crate::{
     common::*,
     config::Config,
+    ratings::Ratings,
     session::{Msg, Session},
 };
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{mpsc, RwLock};
+use std::{collections::HashMap, iter::repeat_with, sync::Arc};
+use tokio::sync::{mpsc, oneshot, RwLock};
+
+/// How many times we try to generate a session ID which is not in use.
+const MAX_SESSION_ID_ATTEMPTS: usize = 32;
+
+pub enum Error {
+    /// The owner name is not a valid user name.
+    InvalidName,
+    /// The maximum number of sessions is reached, or no unused session ID
+    /// could be found.
+    Full,
+}
 
 #[derive(Clone)]
 pub struct Sessions {
@@ -28,22 +40,24 @@ pub struct Sessions {
 
 struct Inner {
     sessions: RwLock<HashMap<SessionId, mpsc::Sender<Msg>>>,
+    ratings: Ratings,
     config: Arc<Config>,
 }
 
 impl Inner {
-    fn new(config: Arc<Config>) -> Self {
+    fn new(config: Arc<Config>, ratings: Ratings) -> Self {
         Self {
             sessions: RwLock::new(HashMap::new()),
+            ratings,
             config,
         }
     }
 }
 
 impl Sessions {
-    pub fn new(config: Arc<Config>) -> Self {
+    pub fn new(config: Arc<Config>, ratings: Ratings) -> Self {
         Self {
-            inner: Arc::new(Inner::new(config)),
+            inner: Arc::new(Inner::new(config, ratings)),
         }
     }
 
@@ -51,24 +65,67 @@ impl Sessions {
         self.inner.sessions.read().await.get(id).cloned()
     }
 
+    /// Returns the summaries of all public sessions.
+    pub async fn list(&self) -> Vec<resp::Listing> {
+        let senders: Vec<_> = self.inner.sessions.read().await.values().cloned().collect();
+        let mut listings = Vec::with_capacity(senders.len());
+        for mut session in senders {
+            let (tx, rx) = oneshot::channel();
+            if session.send(Msg::Listing(tx)).await.is_err() {
+                continue;
+            }
+            if let Ok(Some(listing)) = rx.await {
+                listings.push(listing);
+            }
+        }
+        listings
+    }
+
+    /// Returns the ratings, which are shared by all sessions.
+    pub fn ratings(&self) -> &Ratings {
+        &self.inner.ratings
+    }
+
     pub async fn remove(&self, id: &SessionId) {
         self.inner.sessions.write().await.remove(id);
     }
 
-    pub async fn spawn(&self, owner_name: &str) -> Option<mpsc::Sender<Msg>> {
-        let session_id = SessionId::new();
+    /// Respawns the sessions which were saved to the data directory.
+    pub async fn restore(&self) {
+        for (session, tx) in Session::load_all(self, &self.inner.config) {
+            let session_id = session.id().clone();
+            session.spawn();
+            self.inner.sessions.write().await.insert(session_id, tx);
+        }
+    }
+
+    pub async fn len(&self) -> usize {
+        self.inner.sessions.read().await.len()
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.inner.config.max_session()
+    }
+
+    pub async fn spawn(&self, owner_name: &str) -> Result<mpsc::Sender<Msg>, Error> {
+        let mut sessions = self.inner.sessions.write().await;
+        if sessions.len() >= self.inner.config.max_session() {
+            return Err(Error::Full);
+        }
+        let format = self.inner.config.session_id_format();
+        let session_id = repeat_with(|| SessionId::new(format))
+            .take(MAX_SESSION_ID_ATTEMPTS)
+            .find(|id| !sessions.contains_key(id))
+            .ok_or(Error::Full)?;
         let (session, tx) = Session::new(
             self.clone(),
             self.inner.config.clone(),
             session_id.clone(),
             owner_name,
-        )?;
+        )
+        .ok_or(Error::InvalidName)?;
         session.spawn();
-        self.inner
-            .sessions
-            .write()
-            .await
-            .insert(session_id, tx.clone());
-        Some(tx)
+        sessions.insert(session_id, tx.clone());
+        Ok(tx)
     }
 }